@@ -1,11 +1,11 @@
 use std::{
-    cmp::Ordering, collections::HashMap, fmt::Display, fs::OpenOptions, io::{ self, stdout, Stdout, Write }, path::PathBuf, rc::Rc, sync::{ atomic::AtomicBool, Arc, Mutex }, time::Duration
+    cmp::Ordering, collections::{BTreeMap, HashMap, HashSet}, fmt::Display, fs::OpenOptions, io::{ self, stdout, Stdout, Write }, path::PathBuf, rc::Rc, sync::{ atomic::AtomicBool, Arc, Mutex }, time::Duration
 };
 use std::cmp::{max,min};
 use a2s::info::Info;
 use anyhow::{anyhow, Error};
 use base64::display;
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle, TermLike};
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle, TermLike};
 use log::{error, warn};
 use ratatui::{
     layout::{ Alignment, Constraint, Flex, Layout, Position, Rect },
@@ -24,7 +24,7 @@ use tokio_util::sync::CancellationToken;
 
 use std::cell::{ Cell, RefCell };
 
-use crate::{ClientCtx, LOGO, PROGRESS_STYLE_DOWNLOAD, PROGRESS_STYLE_MESSAGE, TITLE, configs::{CACConfig, CACContent, Config, Links, TMP_FOLDER}, download::download_items, msgraph, servers::{ self, Server }, unzip};
+use crate::{ClientCtx, LOGO, PROGRESS_STYLE_DOWNLOAD, PROGRESS_STYLE_MESSAGE, TITLE, configs::{self, CACConfig, CACContent, Config, ConfigError, Links, TMP_FOLDER}, dirhash::{self, ManifestDiff}, download::{self, download_items}, msgraph, servers::{ self, ModStatus, Server, ServerList }, unzip};
 
 fn center(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
     let [area] = Layout::horizontal([horizontal]).flex(Flex::Center).areas(area);
@@ -99,22 +99,6 @@ impl TermLike for ProgressBarBuffer {
     }
 }
 
-struct UpdateMenu {
-    select: TableState,
-    update_servers: Vec<(String,Server)>,
-    update_items: Vec<(String,String)>
-}
-
-// impl UpdateMenu {
-//     fn Make<'a>(&mut self) -> Result<Table<'a>,Error>{
-        
-//         let ret = Table::new(
-//             Row::new()
-//         )?;
-//         Ok(ret)
-//     }
-// }
-
 struct ServerMenu {
     servers: Vec<(String,Server)>,
     status: Vec<(String, Option<Info>)>,
@@ -186,7 +170,7 @@ impl ServerMenu {
             }).collect::<Vec<_>>();
             let mut launch: bool = true;
             if update_list.len()>0 {
-                launch = ui.popup_update(update_list).await?;
+                launch = ui.popup_update(update_list, false).await?;
                     
             }
             if launch {
@@ -199,27 +183,184 @@ impl ServerMenu {
 }
 
 struct UpdateModsMenu {
-    submenu: TableState
+    /// items marked for a forced redownload
+    marked: HashSet<String>,
+    /// status of every item, or why it couldn't be read. refreshed when the tab opens and after every action
+    status: Result<Vec<(String,ModStatus)>,String>,
+    /// download sizes, filled in the background as graph answers. looked up the first time the tab opens
+    sizes: Option<Arc<Mutex<HashMap<String,u64>>>>,
+    select: TableState
 }
 
 impl UpdateModsMenu {
-    fn make() {
-        let titles = vec![
-            "Update all mods",
-            "Update mods for server",
-            "Redownload mods",
+    fn new() -> Self {
+        Self { marked: HashSet::new(), status: Ok(vec![]), sizes: None, select: TableState::new().with_selected(0) }
+    }
 
-        ];
+    /// re-reads the status of every item, and starts looking up the download sizes if that hasn't been done yet.
+    fn refresh(&mut self) {
+        self.status = CACConfig::read()
+            .and_then(|config| servers::content_status(&config, &CACContent::read()?))
+            .map_err(|e| e.to_string());
+        if let Ok(status) = &self.status {
+            if self.select.selected().is_some_and(|x| x >= status.len()) {
+                self.select.select(Some(status.len().saturating_sub(1)));
+            }
+        }
+        if self.sizes.is_none() {
+            match Self::fetch_sizes() {
+                Ok(sizes) => self.sizes = Some(sizes),
+                Err(e) => warn!("failed to get download sizes: {}",e)
+            }
+        }
     }
 
-    fn key_handler(&mut self,key: KeyEvent) -> () {
-        if key.code == KeyCode::Up{
-            self.submenu.select_previous();
-        }else if key.code == KeyCode::Down {
-            self.submenu.select_next();
-        }else if key.code == KeyCode::Enter {
+    /// looks up the download size of every item in the background.
+    fn fetch_sizes() -> Result<Arc<Mutex<HashMap<String,u64>>>,Error> {
+        let sizes = Arc::new(Mutex::new(HashMap::new()));
+        let items: Vec<_> = CACContent::read()?.content_iter().map(|(k,v)| (k.clone(),v.clone())).collect();
+        let limit = CACConfig::read()?.max_concurrent_downloads;
+        let _sizes = sizes.clone();
+        tokio::spawn(async move {
+            match download::content_sizes(items, limit).await {
+                Ok(s) => *_sizes.lock().unwrap() = s,
+                Err(e) => warn!("failed to get download sizes: {}",e)
+            }
+        });
+        Ok(sizes)
+    }
+
+    fn make(&self) -> Table<'static> {
+        let header = Row::new(vec!["","Mod","Status","Size (\u{2191}/\u{2193}, Space: mark, U: update all, S: update server, F: force redownload, V: verify, R: repair)"]).style(Style::new().fg(Color::LightYellow).bold());
+        let status = match &self.status {
+            Ok(status) => status,
+            Err(e) => {
+                let row = Row::new(vec![Line::from(""), Line::from(vec!["couldn't read the mod status: ".light_red(), e.clone().into()])]);
+                return Table::new([row], [Constraint::Length(3), Constraint::Fill(1)]).header(header);
+            }
+        };
+        let sizes = self.sizes.as_ref().map(|x| x.lock().unwrap());
+        let name_width = status.iter().fold(13, |acc,x| max(acc,x.0.len())) as u16;
+        Table::new(status.iter().map(|(name, s)| {
+            let mark = match self.marked.contains(name) { true => "[x]", false => "[ ]" };
+            let s = match s {
+                ModStatus::UpToDate => s.to_string().light_green(),
+                ModStatus::PendingUpdate => s.to_string().light_yellow(),
+                ModStatus::Missing | ModStatus::Corrupt => s.to_string().light_red(),
+            };
+            let size = sizes.as_ref().and_then(|x| x.get(name)).map(|x| HumanBytes(*x).to_string()).unwrap_or_default();
+            Row::new(vec![Line::from(mark), Line::from(name.clone()), Line::from(s), Line::from(size)])
+        }), [Constraint::Length(3), Constraint::Length(name_width), Constraint::Length(14), Constraint::Fill(1)])
+        .header(header)
+        .row_highlight_style(Style::default().fg(Color::Black).bg(Color::Rgb(66, 149, 0xff)))
+    }
+
+    async fn key_handler(&mut self, ui: &mut TUI, key: KeyEvent) -> Result<(),Error> {
+        let status = self.status.clone().unwrap_or_default();
+        let selected = self.select.selected().unwrap_or(0);
+
+        //failed updates aren't fatal, just tell the user
+        let ret = match key.code {
+            KeyCode::Up => {
+                self.select.select_previous();
+                Ok(())
+            }
+            KeyCode::Down if selected + 1 < status.len() => {
+                self.select.select_next();
+                Ok(())
+            }
+            KeyCode::Char(' ') => {
+                if let Some((name,_)) = status.get(selected) {
+                    if !self.marked.remove(name) {
+                        self.marked.insert(name.clone());
+                    }
+                }
+                Ok(())
+            }
+            KeyCode::Char('u') => Self::update_all(ui, &status).await,
+            KeyCode::Char('s') => Self::update_server(ui).await,
+            KeyCode::Char('f') => self.force_redownload(ui, status.get(selected).map(|x| x.0.clone())).await,
+            KeyCode::Char('v') => ui.popup_verify(false).await.map(|_| ()),
+            KeyCode::Char('r') => Self::repair(ui).await,
+            _ => Ok(())
+        };
+        if let Err(e) = ret {
+            warn!("update failed: {}",e);
+            ui.popup_blocking_prompt(Line::from(vec!["update failed: ".light_red(),e.to_string().into()]).into());
+        }
+        if matches!(key.code, KeyCode::Char('u' | 's' | 'f' | 'v' | 'r')) {
+            self.refresh();
+        }
+        Ok(())
+    }
+
+    /// updates everything that isn't up to date. optional mods that aren't installed are left alone unless the active profile enables them.
+    async fn update_all(ui: &mut TUI, status: &[(String,ModStatus)]) -> Result<(),Error> {
+        let config = CACConfig::read()?;
+        let content = CACContent::read()?;
+        let enabled = &config.profile()?.enabled_optionals;
+        let items: Vec<_> = status.iter().filter(|(name,s)| match s {
+            ModStatus::UpToDate => false,
+            ModStatus::Missing => !content.optionals.contains_key(name) || enabled.contains(name),
+            _ => true
+        }).map(|x| x.0.clone()).collect();
+        if items.is_empty() {
+            ui.popup_blocking_prompt("everything is up to date".light_green().into());
+            return Ok(());
+        }
+        ui.popup_update(items, false).await.map(|_| ())
+    }
+
+    /// asks for a server and updates the mods it needs.
+    async fn update_server(ui: &mut TUI) -> Result<(),Error> {
+        let mut list = servers::update_list()?;
+        list.sort_by(|a,b| a.0.cmp(&b.0));
+        let options: Vec<String> = list.iter().map(|(name,items)| match items.len() {
+            0 => format!("{} (up to date)",name),
+            n => format!("{} ({} to update)",name,n)
+        }).collect();
+        let mut options: Vec<&str> = options.iter().map(|x| x.as_str()).collect();
+        options.push("Cancel");
+        let items = match ui.popup_choice("update the mods for which server?".light_yellow().into(), &options) {
+            Some(i) if i < list.len() => list.swap_remove(i).1,
+            _ => return Ok(())
+        };
+        if items.is_empty() {
+            return Ok(());
+        }
+        ui.popup_update(items, false).await.map(|_| ())
+    }
+
+    /// verifies the installed mods, then fixes the ones that don't match by downloading just the files they need.
+    async fn repair(ui: &mut TUI) -> Result<(),Error> {
+        let diffs: BTreeMap<_,_> = match ui.popup_verify(false).await? {
+            Some(report) => report.into_iter().filter(|(_,d)| !d.is_empty()).collect(),
+            None => return Ok(())
+        };
+        if diffs.is_empty() {
+            return Ok(());
+        }
+        ui.popup_repair(diffs).await.map(|_| ())
+    }
 
+    /// downloads the marked items again from their full archives, or the selected one if nothing is marked.
+    async fn force_redownload(&mut self, ui: &mut TUI, selected: Option<String>) -> Result<(),Error> {
+        let mut items: Vec<String> = match self.marked.is_empty() {
+            true => selected.into_iter().collect(),
+            false => self.marked.iter().cloned().collect()
+        };
+        items.sort();
+        if items.is_empty() {
+            return Ok(());
+        }
+        let txt = format!("redownload {}?",items.join(", "));
+        if ui.popup_choice(Text::from(txt).light_yellow(), &["Redownload", "Cancel"]) != Some(0) {
+            return Ok(());
+        }
+        if ui.popup_update(items, true).await? {
+            self.marked.clear();
         }
+        Ok(())
     }
 }
 
@@ -256,7 +397,9 @@ impl OptionalModsMenu {
     pub fn make(&mut self) -> Result<Table,Error> {
         let content = CACContent::read()?;
         let config = CACConfig::read()?;
-        config.enabled_optionals.iter().for_each(|x| {
+        //the profile may have changed since last time
+        self.titles.iter_mut().for_each(|x| x.1 = OptionalModsStatus::disabled);
+        config.profile()?.enabled_optionals.iter().for_each(|x| {
             if !content.optionals.contains_key(x){
                 warn!("Enabled optional mod '{}' is not present in the content manifest",x);
             }else{
@@ -290,16 +433,16 @@ impl OptionalModsMenu {
             self.select.select_next();
         }else if key.code == KeyCode::Enter {
             let entry = self.titles.get_mut(self.select.selected().unwrap()).unwrap(); 
-            let mut config = CACConfig::read()?;
+            //the update saves the config too, so only change it once that's done
             match entry.1{
                 OptionalModsStatus::not_found => {
                     
-                    let join = ui.popup_update(vec![entry.0.clone()]).await;
+                    let join = ui.popup_update(vec![entry.0.clone()], false).await;
 
                     match join {
                         Ok(b) => {
                             if(b){
-                                config.enabled_optionals.insert(entry.0.clone());
+                                CACConfig::update(|c| Ok(c.profile_mut()?.enabled_optionals.insert(entry.0.clone())))?;
                                 entry.1=OptionalModsStatus::enabled;
                             }else{
                                 ;//was cancelled by user
@@ -312,36 +455,172 @@ impl OptionalModsMenu {
                     }
                 },
                 OptionalModsStatus::enabled => { 
-                    if!(config.enabled_optionals.remove(&entry.0)){
+                    if!(CACConfig::update(|c| Ok(c.profile_mut()?.enabled_optionals.remove(&entry.0)))?){
                         return Err(anyhow!("failed to disable mod {} from the config",&entry.0));
                     }
                     entry.1 = OptionalModsStatus::disabled; 
                 },
                 OptionalModsStatus::disabled => {
-                    config.enabled_optionals.insert(entry.0.clone());
+                    CACConfig::update(|c| Ok(c.profile_mut()?.enabled_optionals.insert(entry.0.clone())))?;
                     entry.1=OptionalModsStatus::enabled;
                 }
 
             }
-            config.save();
         }
         Ok(())
     }
 }
 
-struct LauncherSettingsMenu {
+struct ProfileMenu {
+    select: TableState
+}
 
+impl ProfileMenu {
+    fn new() -> Self {
+        Self { select: TableState::new().with_selected(0) }
+    }
+
+    fn make(&self) -> Result<Table<'static>,Error> {
+        let config = CACConfig::read()?;
+        let name_width = config.profiles.keys().fold(13, |acc,x| max(acc,x.len())) as u16;
+        let user_width = config.profiles.values().fold(13, |acc,x| max(acc,x.username.len())) as u16;
+        Ok(Table::new(config.profiles.iter().map(|(name, p)| {
+            let active = match *name == config.active_profile { true => "[active]", false => "" };
+            Row::new(vec![name.clone(), p.username.clone(), active.to_string()])
+        }), [Constraint::Length(name_width), Constraint::Length(user_width), Constraint::Fill(1)])
+        .header(Row::new(vec!["Profile","Username","(\u{2191}/\u{2193},Enter: switch, N: new, R: rename, D: delete)"]).style(Style::new().fg(Color::LightYellow).bold()))
+        .row_highlight_style(Style::default().fg(Color::Black).bg(Color::Rgb(66, 149, 0xff))))
+    }
+
+    async fn key_handler(&mut self, ui: &mut TUI, key: KeyEvent) -> Result<(),Error> {
+        let names: Vec<String> = CACConfig::read()?.profiles.keys().cloned().collect();
+        let selected = self.select.selected().unwrap_or(0);
+        let name = names.get(selected).cloned().unwrap_or_default();
+
+        //bad names etc. aren't fatal, just tell the user
+        let ret = match key.code {
+            KeyCode::Up => {
+                self.select.select_previous();
+                Ok(())
+            }
+            KeyCode::Down if selected + 1 < names.len() => {
+                self.select.select_next();
+                Ok(())
+            }
+            KeyCode::Enter => CACConfig::update(|c| c.switch_profile(&name)),
+            KeyCode::Char('n') => match ui.popup_text_entry("Enter a name for the new profile") {
+                Some(new) => CACConfig::update(|c| c.add_profile(new.trim())),
+                None => Ok(())
+            },
+            KeyCode::Char('r') => match ui.popup_text_entry(&format!("Enter a new name for '{}'",name)) {
+                Some(new) => CACConfig::update(|c| c.rename_profile(&name, new.trim())),
+                None => Ok(())
+            },
+            KeyCode::Char('d') => match ui.popup_choice(format!("delete profile '{}'?",name).light_yellow().into(), &["Delete", "Cancel"]) {
+                Some(0) => CACConfig::update(|c| c.remove_profile(&name)).map(|_| {
+                    self.select.select(Some(min(selected, names.len().saturating_sub(2))));
+                }),
+                _ => Ok(())
+            },
+            _ => Ok(())
+        };
+        if let Err(e) = ret {
+            warn!("profile change failed: {}",e);
+            ui.popup_blocking_prompt(e.to_string().light_red().into());
+        }
+        Ok(())
+    }
+}
+
+struct LauncherSettingsMenu {
+    select: TableState
 }
 
 impl LauncherSettingsMenu {
-    fn make() -> Result<(),Error>{
+    const ROWS: usize = 3;
+
+    fn new() -> Self {
+        Self { select: TableState::new().with_selected(0) }
+    }
+
+    fn make(&self) -> Result<Table<'static>,Error> {
         let config = CACConfig::read()?;
-        let titles = vec![
-            "Change Username".into(),
-            format!("Change Exile Password [{}]",config.server_password),
-            "Change Mods Directory".into(),
+        let profile = config.profile()?;
+        let password = match profile.server_password.is_empty() {
+            true => "not set".to_string(),
+            false => "*".repeat(profile.server_password.chars().count())
+        };
+        let rows = vec![
+            Row::new(vec!["Change Username".to_string(), profile.username.clone()]),
+            Row::new(vec!["Change Exile Password".to_string(), password]),
+            Row::new(vec!["Change Mods Directory".to_string(), config.mod_dir().to_string()]),
         ];
-        Err(anyhow!("not impl"))
+        Ok(Table::new(rows, [Constraint::Length(24), Constraint::Fill(1)])
+        .header(Row::new(vec!["Setting","Current (\u{2191}/\u{2193},Enter: change)"]).style(Style::new().fg(Color::LightYellow).bold()))
+        .row_highlight_style(Style::default().fg(Color::Black).bg(Color::Rgb(66, 149, 0xff))))
+    }
+
+    async fn key_handler(&mut self, ui: &mut TUI, key: KeyEvent) -> Result<(),Error> {
+        //bad input isn't fatal, just tell the user
+        let ret = match key.code {
+            KeyCode::Up => {
+                self.select.select_previous();
+                Ok(())
+            }
+            KeyCode::Down if self.select.selected().unwrap_or(0) + 1 < Self::ROWS => {
+                self.select.select_next();
+                Ok(())
+            }
+            KeyCode::Enter => match self.select.selected().unwrap_or(0) {
+                0 => match ui.popup_text_entry("Enter your in game username") {
+                    Some(name) => CACConfig::update(|c| c.set_username(&name)),
+                    None => Ok(())
+                },
+                1 => match ui.popup_text_entry("Enter the Exile server password, leave empty to clear it") {
+                    Some(password) => CACConfig::update(|c| c.set_server_password(&password)),
+                    None => Ok(())
+                },
+                _ => match ui.popup_text_entry("Enter the mods folder, absolute or relative to the arma folder") {
+                    Some(dir) => Self::change_mod_dir(ui, &dir.replace("\"", "")).await, //trim quotes from windows 'copy as path'
+                    None => Ok(())
+                }
+            },
+            _ => Ok(())
+        };
+        if let Err(e) = ret {
+            warn!("settings change failed: {}",e);
+            ui.popup_blocking_prompt(e.to_string().light_red().into());
+        }
+        Ok(())
+    }
+
+    /// points the config at `dir`, offering to move the content items installed in the old folder.
+    async fn change_mod_dir(ui: &mut TUI, dir: &str) -> Result<(),Error> {
+        let config = CACConfig::read()?;
+        let old = config.absolute_mod_dir()?;
+        let mut changed = config.clone();
+        changed.set_mod_dir(dir)?;
+        let new = changed.absolute_mod_dir()?;
+
+        let content = CACContent::read()?;
+        let installed: Vec<String> = content.mods.keys().chain(content.optionals.keys()).filter(|n| old.join(n).exists()).cloned().collect();
+        if old != new && !installed.is_empty() {
+            let txt = format!("move the {} installed mods from\n'{}'\nto\n'{}'?",installed.len(),old.display(),new.display());
+            match ui.popup_choice(Text::from(txt).light_yellow(), &["Move mods", "Leave them", "Cancel"]) {
+                Some(0) => {
+                    if new.starts_with(&old) || old.starts_with(&new) {
+                        return Err(anyhow!("can't move mods between '{}' and '{}' as one is inside the other",old.display(),new.display()));
+                    }
+                    ui.popup_message("moving mods...");
+                    let (from, to) = (old.clone(), new.clone());
+                    //anything that couldn't be moved back is named in the error, the config keeps the old folder
+                    tokio::task::spawn_blocking(move || download::move_entries(&from, &to, &installed)).await??;
+                }
+                Some(1) => {}
+                _ => return Ok(())
+            }
+        }
+        CACConfig::update(|c| c.set_mod_dir(dir))
     }
 }
 
@@ -456,7 +735,7 @@ impl TUI {
                         }
                     }
                     KeyCode::Delete => {
-                        if cur >= 0 && cur < buf.len() {
+                        if cur < buf.len() {
                             buf.remove(cur);
                         }
                     }
@@ -469,12 +748,12 @@ impl TUI {
     }
 
     ///this function blocks until recieves finish signal or user request to cancel.
-    /// intended for handling downloads
+    /// intended for handling downloads. each non-empty buffer in `pbufs` is drawn as its own line.
     /// # Returns:
     /// false if task was cancelled.
     pub fn popup_progress(
         &mut self,
-        pbuf: Arc<Mutex<RefCell<String>>>,
+        pbufs: Vec<Arc<Mutex<RefCell<String>>>>,
         title_buf: Arc<Mutex<String>>,
         finish: CancellationToken
     ) -> bool {
@@ -485,25 +764,29 @@ impl TUI {
             }
         
             let panel: Paragraph;
-            let v: String;
-            let t: String;
-            {
+            let lines: Vec<String> = pbufs.iter().filter_map(|pbuf| {
                 let lock = pbuf.lock().unwrap();
-                v = lock.borrow().clone();
-            }
+                let v = lock.borrow().clone();
+                match v.len() {
+                    0 => None,
+                    _ => Some(v)
+                }
+            }).collect();
             let block = Block::bordered()
                 .title_bottom("Press C to cancel")
                 .title_top(title_buf.lock().unwrap().clone())
                 .title_alignment(Alignment::Center);
-            if v.len() == 0 {
+            if lines.is_empty() {
                 log::info!("detected ProgressBarBuffer len==0 (bug to fix)"); //TODO why is this happening...
                 continue;
             }
-            if v.len() != prev_len {
+            let len = lines.iter().fold(0, |acc,x| acc + x.len());
+            if len != prev_len {
                 self.term.clear();
             }
-            prev_len = v.len();
-            panel = Paragraph::new(v).block(block).centered();
+            prev_len = len;
+            let height = lines.len() as u16;
+            panel = Paragraph::new(Text::from(lines.into_iter().map(Line::from).collect::<Vec<_>>())).block(block).centered();
             self.term.draw(|x| {
                 let width = panel.line_width() as u16;
                 panel
@@ -512,7 +795,7 @@ impl TUI {
                         center(
                             x.area(),
                             Constraint::Length(width + 2),
-                            Constraint::Length(3 as u16)
+                            Constraint::Length(height + 2)
                         ),
                         x.buffer_mut()
                     );
@@ -532,39 +815,118 @@ impl TUI {
     }
 
     /// popup to wrap download + unzip mod. will remove items from CACConfig->pending_updates after completing update.
+    /// runs up to `CACConfig::max_concurrent_downloads` transfers at once, with a progress line for each plus one for extraction.
     /// # Return:
     /// Returns false if operation cancelled by user.
-    pub async fn popup_update(&mut self, items: Vec<String>) -> Result<bool,Error> {
+    /// downloads and installs `items`, showing their progress. `force` downloads the full archives even if a delta update would do.
+    pub async fn popup_update(&mut self, items: Vec<String>, force: bool) -> Result<bool,Error> {
         warn!("UI: entered popup_update");
+        let title = format!("Update items: 0/{}",items.len());
+        self.popup_transfers(title, move |bars, extract_progress, title_buf, finish| tokio::spawn(download_items(items, force, bars, extract_progress, title_buf, finish))).await
+    }
+
+    /// repairs the mods in `diffs` from just the files they need, showing their progress.
+    /// Returns false if operation cancelled by user.
+    pub async fn popup_repair(&mut self, diffs: BTreeMap<String,ManifestDiff>) -> Result<bool,Error> {
+        let title = format!("Repair items: 0/{}",diffs.len());
+        self.popup_transfers(title, move |bars, extract_progress, title_buf, finish| tokio::spawn(download::repair_items(diffs, bars, extract_progress, title_buf, finish))).await
+    }
 
+    /// shows a bar per download slot plus one for extraction while the task from `start` runs.
+    async fn popup_transfers(&mut self, title: String, start: impl FnOnce(Vec<ProgressBar>, ProgressBar, Arc<Mutex<String>>, CancellationToken) -> JoinHandle<Result<bool,Error>>) -> Result<bool,Error> {
         let term_size = self.term.size()?;
-        let mut progressBuf = ProgressBarBuffer::new(); 
-        let mut pbuf = progressBuf.buffer.clone();
-        let mut progress = ProgressBar::new((term_size.width /2) as u64).with_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
-        progress.set_length(1); progress.set_position(0);
-        progress.set_draw_target(ProgressDrawTarget::term_like(Box::new(progressBuf)));
+        let slots = CACConfig::read()?.max_concurrent_downloads.max(1);
+
+        //last bar is used for extraction
+        let mut pbufs = Vec::new();
+        let mut bars = Vec::new();
+        for _ in 0..slots+1 {
+            let progressBuf = ProgressBarBuffer::new();
+            pbufs.push(progressBuf.buffer.clone());
+            let progress = ProgressBar::new((term_size.width /2) as u64).with_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+            progress.set_length(1); progress.set_position(0);
+            progress.set_draw_target(ProgressDrawTarget::term_like(Box::new(progressBuf)));
+            bars.push(progress);
+        }
+        let extract_progress = bars.pop().unwrap();
 
-        let title_buf = Arc::new(Mutex::new(format!("Update items: 0/{}",items.len())));
+        let title_buf = Arc::new(Mutex::new(title));
         let _title_buf = title_buf.clone();
         
         let _finish = CancellationToken::new();
         let finish = _finish.clone();
 
-        let join: JoinHandle<Result<bool,Error>>  =tokio::spawn(download_items(items, progress, title_buf, finish));
+        let join = start(bars, extract_progress, title_buf, finish);
 
-        let ret = if !self.popup_progress(pbuf, _title_buf,_finish.clone()){
+        let ret = if !self.popup_progress(pbufs, _title_buf,_finish.clone()){
             _finish.cancel();
             false
         }else {
             true
         };
 
-        join.await??; //this error almost got away... JoinError then download 
-        warn!("UI:popup_update ok");
+        let ret = join.await?? && ret; //this error almost got away... JoinError then download 
+        warn!("UI:popup_transfers ok");
         self.term.clear();
         Ok(ret)
     }
 
+    /// hashes the installed mods against the published manifest, queues any that don't match for update, and shows what differs.
+    /// # Returns
+    /// how each mod differs, None if verification was cancelled or failed.
+    pub async fn popup_verify(&mut self, force: bool) -> Result<Option<BTreeMap<String,ManifestDiff>>,Error> {
+        let term_size = self.term.size()?;
+        let progressBuf = ProgressBarBuffer::new();
+        let pbufs = vec![progressBuf.buffer.clone()];
+        let progress = ProgressBar::new((term_size.width /2) as u64);
+        progress.set_draw_target(ProgressDrawTarget::term_like(Box::new(progressBuf)));
+        let title_buf = Arc::new(Mutex::new("Verifying mods".to_string()));
+
+        //cancelled by the task when it's done, or by us if the user cancels
+        let finish = CancellationToken::new();
+        let _finish = finish.clone();
+        let join = tokio::task::spawn_blocking(move || {
+            let config = CACConfig::read()?;
+            let ret = dirhash::verify_installed_mods(&config, force, &dirhash::HashProgress::new(Some(progress)), &_finish);
+            _finish.cancel();
+            ret
+        });
+
+        if !self.popup_progress(pbufs, title_buf, finish.clone()) {
+            finish.cancel();
+        }
+        let report = join.await?;
+        self.term.clear();
+
+        let report = match report {
+            Ok(r) => r,
+            Err(e) if matches!(e.downcast_ref(), Some(dirhash::DirHashError::Cancelled)) => {
+                warn!("verification cancelled");
+                return Ok(None);
+            }
+            Err(e) => {
+                error!("verification failed: {}",e);
+                self.popup_blocking_prompt(Line::from(vec!["verification failed: ".light_red(),e.to_string().into()]).into());
+                return Ok(None);
+            }
+        };
+
+        let bad: Vec<_> = report.iter().filter(|(_,d)| !d.is_empty()).collect();
+        let mut txt = Text::default();
+        for (name, diff) in &bad {
+            txt.push_line(format!("{}:",name).light_red().bold());
+            for l in diff.report(5) {
+                txt.push_line(Line::from(l));
+            }
+        }
+        txt.push_line(match bad.len() {
+            0 => format!("all {} installed mods are ok",report.len()).light_green(),
+            n => format!("{} of {} mods don't match and have been queued for update",n,report.len()).light_yellow()
+        });
+        self.popup_blocking_prompt(txt);
+        Ok(Some(report))
+    }
+
     /// this function will block until user enters any key input to the popup prompt.
     /// border shrinks to fit lines of text. there is no limit on the maximum text line size.
     pub fn popup_blocking_prompt(&mut self, mut txt: Text) {
@@ -601,6 +963,121 @@ impl TUI {
         self.term.clear();
     }
 
+    /// blocks until the user picks one of `options` with the arrow keys and enter.
+    /// # Returns
+    /// the index of the option picked, or None if cancelled with Esc.
+    pub fn popup_choice(&mut self, txt: Text, options: &[&str]) -> Option<usize> {
+        let mut select = 0;
+        self.term.clear();
+        loop {
+            let mut lines = txt.clone();
+            lines.push_line("");
+            for (i, o) in options.iter().enumerate() {
+                lines.push_line(match i == select {
+                    true => format!("> {} <",o).black().on_green(),
+                    false => o.to_string().green()
+                });
+            }
+            let footer = "[Up/Down: Select, Enter: Confirm, Esc: Cancel]";
+            let block = Block::bordered().title_bottom(footer).title_alignment(Alignment::Center);
+            let panel = Paragraph::new(lines.clone()).block(block).centered();
+            self.term.draw(|x| {
+                let width = max(panel.line_width(), footer.len()) as u16;
+                panel
+                    .clone()
+                    .render(
+                        center(
+                            x.area(),
+                            Constraint::Length(width + 2),
+                            Constraint::Length((lines.height() + 2) as u16)
+                        ),
+                        x.buffer_mut()
+                    );
+            });
+
+            let event = read().unwrap();
+            if event.is_key_press() {
+                match event.as_key_press_event().unwrap().code {
+                    KeyCode::Up => select = select.saturating_sub(1),
+                    KeyCode::Down => select = min(select + 1, options.len() - 1),
+                    KeyCode::Enter => {
+                        self.term.clear();
+                        return Some(select);
+                    }
+                    KeyCode::Esc => {
+                        self.term.clear();
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// checks config.json, content.json and servers.json can be read, see [TUI::recover_config].
+    pub fn recover_configs(&mut self) -> Result<(),Error> {
+        self.recover_config::<CACConfig>(|ui, salvaged| {
+            //don't ask for the arma path again if it survived
+            match salvaged.get("armaPath").and_then(|p| p.as_str()) {
+                Some(ap) if std::path::Path::new(ap).is_file() => Ok(CACConfig::with_arma_path(ap.to_string())),
+                _ => CACConfig::default(ui)
+            }
+        })?;
+        self.recover_config::<CACContent>(|_, _| Ok(CACContent::default()))?;
+        self.recover_config::<ServerList>(|_, _| Ok(ServerList::default()))?;
+        Ok(())
+    }
+
+    /// if the config `T` exists but is corrupted, offers to restore its last good backup, rebuild it from `defaults`
+    /// keeping whatever can still be read, or open its location to fix by hand. asks again until it can be read.
+    /// # Returns
+    /// the parse error if the user gives up.
+    fn recover_config<T: Config>(&mut self, defaults: fn(&mut TUI, &serde_json::Map<String,serde_json::Value>) -> Result<T,Error>) -> Result<(),Error> {
+        let path = T::file_path();
+        loop {
+            if !path.is_file() {
+                return Ok(());
+            }
+            let err = match T::read() {
+                Ok(_) => return Ok(()),
+                Err(e) => e
+            };
+            if !matches!(err.downcast_ref(), Some(ConfigError::Corrupt { .. })) {
+                return Err(err);
+            }
+            error!("{}",err);
+
+            let mut options = Vec::new();
+            if configs::has_backup::<T>(&path) {
+                options.push("Restore last good backup");
+            }
+            options.extend(["Rebuild from defaults", "Open file location", "Quit"]);
+            let txt = Text::from(vec![Line::from(err.to_string()).light_red(), "what do you want to do?".into()]);
+            match self.popup_choice(txt, &options).map(|i| options[i]) {
+                Some("Restore last good backup") => {
+                    if !configs::restore_backup::<T>(&path)? {
+                        self.popup_blocking_prompt("none of the backups could be read either".light_red().into());
+                    }
+                }
+                Some("Rebuild from defaults") => {
+                    let kept = configs::rebuild::<T>(&path, |salvaged| defaults(self, salvaged))?;
+                    let msg = match kept.is_empty() {
+                        true => "nothing could be salvaged, rebuilt from defaults".to_string(),
+                        false => format!("rebuilt, salvaged: {}",kept.join(", "))
+                    };
+                    self.popup_blocking_prompt(msg.light_yellow().into());
+                }
+                Some("Open file location") => {
+                    if let Err(e) = open_location(&path) {
+                        error!("failed to open '{}': {}",path.display(),e);
+                    }
+                    self.popup_blocking_prompt(format!("fix '{}', then continue",path.display()).into());
+                }
+                _ => return Err(err)
+            }
+        }
+    }
+
     pub fn warn_unknown_mod_state(&mut self) {
         let mut txt =
             "current mod state is unknown, assuming all mods are up to date.\n\
@@ -664,9 +1141,10 @@ impl TUI {
             select: TableState::new().with_selected(0)
         };
 
-        //let mut update_mods_menu = UpdateModsMenu::new();
+        let mut update_mods_menu = UpdateModsMenu::new();
         let mut optional_mods_menu  = OptionalModsMenu::new()?;
-        //let mut launcher_settings_menu = LauncherSettingsMenu::new();
+        let mut profile_menu = ProfileMenu::new();
+        let mut launcher_settings_menu = LauncherSettingsMenu::new();
         
         loop {
             let tabs = self.main_menu(&titles);
@@ -684,14 +1162,21 @@ impl TUI {
                         x.render_stateful_widget(server_menu.make().unwrap(), Rect::new(0,3,term_size.width,term_size.height.saturating_sub(3)), &mut server_menu.select);
                     }
                     "Update Mods" => {
-                        
+                        let mut s = update_mods_menu.select.clone();
+                        x.render_stateful_widget(update_mods_menu.make(), Rect::new(0,3,term_size.width,term_size.height.saturating_sub(3)), &mut s);
                     }
                     "Optional Mods"  => {
                         let mut s = optional_mods_menu.select.clone(); 
                         x.render_stateful_widget(optional_mods_menu.make().unwrap(), Rect::new(0,3,term_size.width,term_size.height.saturating_sub(3)), &mut s);
                     }
-                    "Change User Profile"  => {}
-                    "Launcher Settings"  => {}
+                    "Change User Profile"  => {
+                        let mut s = profile_menu.select.clone();
+                        x.render_stateful_widget(profile_menu.make().unwrap(), Rect::new(0,3,term_size.width,term_size.height.saturating_sub(3)), &mut s);
+                    }
+                    "Launcher Settings"  => {
+                        let mut s = launcher_settings_menu.select.clone();
+                        x.render_stateful_widget(launcher_settings_menu.make().unwrap(), Rect::new(0,3,term_size.width,term_size.height.saturating_sub(3)), &mut s);
+                    }
                     _ => {}
                 }
             });
@@ -700,6 +1185,7 @@ impl TUI {
 
             if event.is_key_press() {
                 let key = event.as_key_event().unwrap();
+                let prev_tab = tab_select;
                 if key.code == KeyCode::Left {
                     tab_select = tab_select.saturating_sub(1);
                 } else if key.code == KeyCode::Right && tab_select < titles.len() - 1 {
@@ -707,25 +1193,25 @@ impl TUI {
                 }else if key.code == KeyCode::Esc || key.code == KeyCode::Char('q') {
                     return Ok(());
                 } 
+                if tab_select != prev_tab && titles[tab_select] == "Update Mods" {
+                    update_mods_menu.refresh();
+                }
 
                 match titles[tab_select] {
                     "Connect" => {
                         if !server_menu.key_handler(self,key).await? {return Ok(());}
                     }
                     "Update Mods" => {
-
+                        update_mods_menu.key_handler(self,key).await?;
                     }
                     "Optional Mods" => {
                         optional_mods_menu.key_handler(self,key).await;
                     }
                     "Change User Profile" => {
+                        profile_menu.key_handler(self,key).await?;
                     }
                     "Launcher Settings" => {
-                        if key.code == KeyCode::Up {
-
-                        }else if key.code == KeyCode::Down {
-
-                        }
+                        launcher_settings_menu.key_handler(self,key).await?;
                     }
                     _ => {}
                 }
@@ -742,3 +1228,23 @@ impl Drop for TUI {
         disable_raw_mode();
     }
 }
+
+/// opens the folder containing `path` in the system file manager.
+fn open_location(path: &std::path::Path) -> Result<(),Error> {
+    let path = std::path::absolute(path)?;
+    let mut cmd = if cfg!(windows) {
+        let mut c = std::process::Command::new("explorer");
+        c.arg(format!("/select,{}",path.display()));
+        c
+    } else if cfg!(target_os = "macos") {
+        let mut c = std::process::Command::new("open");
+        c.arg("-R").arg(&path);
+        c
+    } else {
+        let mut c = std::process::Command::new("xdg-open");
+        c.arg(path.parent().ok_or(anyhow!("'{}' has no parent folder",path.display()))?);
+        c
+    };
+    cmd.spawn()?;
+    Ok(())
+}
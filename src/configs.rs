@@ -1,62 +1,418 @@
-use std::{collections::{HashMap, HashSet}, fs::{File, OpenOptions}, io::Read, path::{self, Path, PathBuf}};
-use crate::{UI::TUI};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::{File, OpenOptions}, io::{BufWriter, Read, Write}, path::{self, Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+use crate::{UI::TUI, dirhash::{FolderCache, ModsManifest}, download::{RetryPolicy, remove_path}};
 use anyhow::{anyhow,Error};
 use chrono::format::StrftimeItems;
-use log::warn;
+use log::{info, warn};
+use serde_json::{Map, Value};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
-pub const CONFIG_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
-    PathBuf::from("CAC-Config")
+/// environment variable to put the config folder somewhere else, see [resolve_config_folder].
+pub const CONFIG_DIR_ENV: &str = "CAC_LAUNCHER_CONFIG_DIR";
+/// a file with this name next to the launcher exe keeps the config in `CAC-Config` beside it.
+pub const PORTABLE_MARKER: &str = "CAC-Launcher.portable";
+const PORTABLE_FOLDER: &str = "CAC-Config";
+
+static CONFIG_DIR_OVERRIDE: once_cell::sync::OnceCell<PathBuf> = once_cell::sync::OnceCell::new();
+
+/// uses `path` as the config folder, e.g. from `--config-dir`. has to be called before anything uses [CONFIG_FOLDER].
+pub fn set_config_dir(path: PathBuf) -> Result<(),Error> {
+    if Lazy::get(&CONFIG_FOLDER).is_some() {
+        return Err(anyhow!("config folder already resolved to '{}'",CONFIG_FOLDER.display()));
+    }
+    CONFIG_DIR_OVERRIDE.set(path).map_err(|p| anyhow!("config folder already set to '{}'",p.display()))
+}
+
+/// the standard per-user config folder for the platform, or None if the environment doesn't say where home is.
+pub fn platform_config_dir() -> Option<PathBuf> {
+    let env = |k| std::env::var_os(k).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if cfg!(windows) {
+        env("APPDATA").map(|p| p.join("CAC-Launcher"))
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|p| p.join("Library").join("Application Support").join("CAC-Launcher"))
+    } else {
+        env("XDG_CONFIG_HOME").or_else(|| env("HOME").map(|p| p.join(".config"))).map(|p| p.join("cac-launcher"))
+    }
+}
+
+/// picks the config folder from, in order: `flag`, the `env` value, `exe_dir/CAC-Config` if [PORTABLE_MARKER] is next to the exe,
+/// then the `platform` config folder, see [platform_config_dir].
+/// installs from before this that already have `CAC-Config` next to the exe keep using it, as do ones that have it in the
+/// working directory `cwd` so long as the platform config folder has no config yet.
+pub fn resolve_config_folder(flag: Option<&Path>, env: Option<&std::ffi::OsStr>, exe_dir: Option<&Path>, cwd: Option<&Path>, platform: Option<PathBuf>) -> PathBuf {
+    if let Some(p) = flag {
+        return p.to_path_buf();
+    }
+    if let Some(p) = env.filter(|e| !e.is_empty()) {
+        return PathBuf::from(p);
+    }
+    if let Some(dir) = exe_dir {
+        let portable = dir.join(PORTABLE_FOLDER);
+        if dir.join(PORTABLE_MARKER).is_file() || portable.join("config.json").is_file() {
+            return portable;
+        }
+    }
+    if let Some(dir) = cwd {
+        let old = dir.join(PORTABLE_FOLDER);
+        if old.join("config.json").is_file() && platform.as_ref().is_none_or(|p| !p.join("config.json").is_file()) {
+            warn!("using the config folder '{}' in the working directory, move it to '{}' to use it from anywhere",
+                old.display(), platform.as_ref().map(|p| p.display().to_string()).unwrap_or_default());
+            return old;
+        }
+    }
+    platform.unwrap_or(PathBuf::from(PORTABLE_FOLDER))
+}
+
+pub static CONFIG_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
+    let exe_dir = std::env::current_exe().ok().and_then(|e| e.parent().map(Path::to_path_buf));
+    let env = std::env::var_os(CONFIG_DIR_ENV);
+    resolve_config_folder(CONFIG_DIR_OVERRIDE.get().map(PathBuf::as_path), env.as_deref(), exe_dir.as_deref(), Some(Path::new(".")), platform_config_dir())
 });
-pub const LOG_PATH: Lazy<PathBuf> = Lazy::new(|| {
+pub static LOG_PATH: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("CAC-Launcher.log")
 });
-pub const TMP_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
+pub static TMP_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("tmp")
 });
 
-pub const SERVERS_FILE: Lazy<PathBuf> = Lazy::new(|| {
+pub static SERVERS_FILE: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("servers.json")
 });
-pub const CONFIG_FILE: Lazy<PathBuf> = Lazy::new(|| {
+pub static CONFIG_FILE: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("config.json")
 });
 
-pub const CONTENT_FILE: Lazy<PathBuf> = Lazy::new(|| {
+pub static CONTENT_FILE: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("content.json")
 });
 
 /// associated ID's for downloaded temp files.
 /// 7zip needs parts to be named @name.7z.00x
-pub const TMP_DOWNLOADS_FILE: Lazy<PathBuf> = Lazy::new(|| {
+pub static TMP_DOWNLOADS_FILE: Lazy<PathBuf> = Lazy::new(|| {
     CONFIG_FOLDER.join("tmp-downloads.json")
 });
 
+/// per-file hashes of installed mods from previous passes, so unchanged files aren't read again.
+pub static HASH_CACHE_FILE: Lazy<PathBuf> = Lazy::new(|| {
+    CONFIG_FOLDER.join("hash-cache.json")
+});
+
+/// per-file manifests of each mod, published next to content.json.
+pub static MODS_MANIFEST_FILE: Lazy<PathBuf> = Lazy::new(|| {
+    CONFIG_FOLDER.join("mods-manifest.json")
+});
+
+/// writes `value` as json to a temp file next to `path`, syncs it, then renames it over `path`.
+/// a crash part way through leaves either the old or the new file, never a truncated one.
+fn write_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(),Error> {
+    //unique per writer, so concurrent saves don't write into each others temp file
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let fname = path.file_name().ok_or(anyhow!("'{}' is not a file path",path.display()))?.to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.{}.{}.tmp",fname,std::process::id(),COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+    let written = (|| -> Result<(),Error> {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut writer, value)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written.and_then(|_| Ok(std::fs::rename(&tmp, path)?)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    //make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// an advisory lock on a config file, released when dropped. see [Config::lock].
+/// it's taken on a separate `.lock` file next to the config, as saving replaces the config file itself.
+pub struct ConfigLock(File);
+
+impl ConfigLock {
+    /// blocks until no one else holds the lock for `path`, including other handles in this process.
+    pub fn acquire(path: &Path) -> Result<Self,Error> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(PathBuf::from(lock_path))?;
+        file.lock()?;
+        Ok(Self(file))
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("'{path}' is corrupted: {reason}")]
+    Corrupt { path: PathBuf, reason: String },
+}
+
+fn corrupt(path: &Path, reason: impl ToString) -> Error {
+    ConfigError::Corrupt { path: path.to_path_buf(), reason: reason.to_string() }.into()
+}
+
+/// the `n`th most recent backup of the config at `path`, see [Config::BACKUPS].
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(format!(".{}.bak",n));
+    PathBuf::from(p)
+}
+
+/// shifts the backups of `path` along, dropping the oldest, and copies `path` in as the newest.
+/// called before a save, so the backups are the versions before the current one.
+fn rotate_backups(path: &Path, count: usize) -> Result<(),Error> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    for n in (1..count).rev() {
+        if backup_path(path, n).is_file() {
+            std::fs::rename(backup_path(path, n), backup_path(path, n+1))?;
+        }
+    }
+    std::fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// replaces the config at `path` with its most recent backup that can still be read.
+/// # Returns
+/// false if there's no usable backup.
+pub fn restore_backup<T: Config>(path: &Path) -> Result<bool,Error> {
+    for n in 1..=T::BACKUPS {
+        let backup = backup_path(path, n);
+        if !backup.is_file() {
+            continue;
+        }
+        match T::read_from(&backup) {
+            Ok(config) => {
+                warn!("restoring '{}' from '{}'",path.display(),backup.display());
+                //not save_to, rotating would push the corrupt file into the backups
+                write_atomic(path, &config)?;
+                return Ok(true);
+            }
+            Err(e) => warn!("backup '{}' is unusable: {}",backup.display(),e)
+        }
+    }
+    Ok(false)
+}
+
+pub fn has_backup<T: Config>(path: &Path) -> bool {
+    (1..=T::BACKUPS).any(|n| backup_path(path, n).is_file())
+}
+
+/// reads as many complete fields from the json object in `text` as it can, stopping at the first error.
+/// an object cut off part way is salvaged the same way, so a truncated file keeps everything before the cut.
+pub fn salvage_object(text: &str) -> Map<String,Value> {
+    let mut ret = Map::new();
+    let Some(start) = text.find('{') else {
+        return ret;
+    };
+    let mut rest = &text[start+1..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        let mut keys = serde_json::Deserializer::from_str(rest).into_iter::<String>();
+        let key = match keys.next() {
+            Some(Ok(k)) => k,
+            _ => return ret
+        };
+        let Some(value) = rest[keys.byte_offset()..].trim_start().strip_prefix(':') else {
+            return ret;
+        };
+        rest = value.trim_start();
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        match values.next() {
+            Some(Ok(v)) => {
+                ret.insert(key, v);
+                rest = &rest[values.byte_offset()..];
+            }
+            _ => {
+                if rest.starts_with('{') {
+                    ret.insert(key, Value::Object(salvage_object(rest)));
+                }
+                return ret;
+            }
+        }
+    }
+}
+
+/// fills in `defaults` with each of the `salvaged` fields that still fits the config.
+/// # Returns
+/// the config and the names of the fields kept.
+pub fn salvage<T: Config>(salvaged: &Map<String,Value>, defaults: T) -> Result<(T, Vec<String>),Error> {
+    let mut base = serde_json::to_value(defaults)?;
+    let mut kept = Vec::new();
+    for (k, v) in salvaged {
+        let mut trial = base.clone();
+        trial.as_object_mut().ok_or(anyhow!("config is not a json object"))?.insert(k.clone(), v.clone());
+        if serde_json::from_value::<T>(trial.clone()).is_ok() {
+            base = trial;
+            kept.push(k.clone());
+        }
+    }
+    Ok((serde_json::from_value(base)?, kept))
+}
+
+/// rewrites the corrupted config at `path` from `defaults`, keeping every field that can still be read from it, see [salvage].
+/// what's salvaged is migrated first, so fields from an older version still fit.
+/// `defaults` is given what was salvaged to base the defaults on. the corrupted file is kept as `<path>.corrupt`.
+/// # Returns
+/// the names of the fields kept.
+pub fn rebuild<T: Config>(path: &Path, defaults: impl FnOnce(&Map<String,Value>) -> Result<T,Error>) -> Result<Vec<String>,Error> {
+    let text = String::from_utf8_lossy(&std::fs::read(path)?).to_string();
+    let mut salvaged = salvage_object(&text);
+    if !T::MIGRATIONS.is_empty() {
+        migrate(path, &mut salvaged, T::MIGRATIONS)?;
+    }
+    let (config, kept) = salvage(&salvaged, defaults(&salvaged)?)?;
+
+    let mut corrupt = path.as_os_str().to_owned();
+    corrupt.push(".corrupt");
+    std::fs::copy(path, PathBuf::from(corrupt))?;
+    //not save_to, the corrupt file is kept above rather than in the backups
+    write_atomic(path, &config)?;
+    warn!("rebuilt '{}', kept {:?}",path.display(),kept);
+    Ok(kept)
+}
+
+/// upgrades a config's json by one version, see [Config::MIGRATIONS].
+pub type Migration = fn(&mut Map<String,Value>) -> Result<(),Error>;
+
+/// brings the config json read from `path` up to `migrations.len()`, its `version` key saying how many have been applied already.
+/// configs from a newer launcher are left as they are.
+/// # Returns
+/// the version it was at if anything changed.
+fn migrate(path: &Path, obj: &mut Map<String,Value>, migrations: &[Migration]) -> Result<Option<usize>,Error> {
+    let version = match obj.get("version") {
+        None => 0,
+        Some(v) => v.as_u64().ok_or(anyhow!("version in '{}' is not a number",path.display()))? as usize
+    };
+    let current = migrations.len();
+    if version > current {
+        warn!("'{}' is version {} but this launcher only knows up to {}, reading it anyway",path.display(),version,current);
+    }
+    if version >= current {
+        return Ok(None);
+    }
+
+    for (v, m) in migrations.iter().enumerate().skip(version) {
+        info!("migrating '{}' from version {} to {}",path.display(),v,v+1);
+        m(obj)?;
+    }
+    obj.insert("version".into(), current.into());
+    Ok(Some(version))
+}
+
+/// reads and migrates the config at `path`, see [Config::read_from]. `locked` says whether the caller holds the config lock already.
+fn read_config<T: Config>(path: &Path, locked: bool) -> Result<T,Error> {
+    let mut config_buf = String::new();
+    let mut config_file = File::open(path)?;
+    config_file.read_to_string(&mut config_buf).map_err(|e| corrupt(path, e))?;
+    if T::MIGRATIONS.is_empty() {
+        return serde_json::from_str::<T>(config_buf.as_str()).map_err(|e| corrupt(path, e));
+    }
+
+    let mut value: Map<String,Value> = serde_json::from_str(config_buf.as_str()).map_err(|e| corrupt(path, e))?;
+    let outdated = match value.get("version") {
+        None => true,
+        Some(v) => v.as_u64().is_none_or(|v| (v as usize) < T::MIGRATIONS.len())
+    };
+    if outdated && !locked {
+        //read again under the lock, it may have changed or been migrated by someone else meanwhile
+        let _lock = T::lock_at(path)?;
+        return read_config(path, true);
+    }
+    //a copy of the old file is kept as `<path>.v<old version>.bak`
+    if let Some(version) = migrate(path, &mut value, T::MIGRATIONS)? {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak",version));
+        std::fs::copy(path, PathBuf::from(backup))?;
+        write_atomic(path, &value)?;
+    }
+    serde_json::from_value::<T>(Value::Object(value)).map_err(|e| corrupt(path, e))
+}
+
+/// a json config file. each has a default location from [Config::file_path], the `_to`/`_from`/`_at` variants work on any path
+/// e.g. for profiles, exports or tests.
 pub trait Config: Serialize + for<'de> Deserialize<'de> {
     fn file_path() -> PathBuf;
 
+    /// upgrades from older versions, `MIGRATIONS[n]` takes version n to n+1 so the current version is `MIGRATIONS.len()`.
+    /// configs with any migrations need a `version` field, and should keep unknown fields to pass on to newer launchers.
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    /// how many copies of the last successful saves to keep for [restore_backup].
+    const BACKUPS: usize = 0;
+
+    /// saves atomically to `path`, see [write_atomic], then rotates the backups. doesn't lock, use [Config::update_at] to change what's on disk.
     fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(),Error> {
-        let f = OpenOptions::new().truncate(true).write(true).create(true).open(Self::file_path())?;
-        serde_json::to_writer_pretty(f, &self)?;
-        Ok(())
+        if Self::BACKUPS > 0 {
+            rotate_backups(path.as_ref(), Self::BACKUPS)?;
+        }
+        write_atomic(path.as_ref(), self)
     }
 
     fn save(&self) -> Result<(),Error> {
         Self::save_to(self,Self::file_path())
     }
-    
 
+    /// takes the advisory lock on the config file at `path`, so the launcher, the downloader and background tasks can't interleave a read-modify-write.
+    fn lock_at<P: AsRef<Path>>(path: P) -> Result<ConfigLock,Error> {
+        ConfigLock::acquire(path.as_ref())
+    }
+
+    fn lock() -> Result<ConfigLock,Error> {
+        Self::lock_at(Self::file_path())
+    }
 
-    fn _read(path: PathBuf) -> Result<Self,Error> {
-        let mut config_buf = String::new();
-        let mut config_file = File::open(path)?;
-        config_file.read_to_string(&mut config_buf)?;
-        Ok(serde_json::from_str::<Self>(config_buf.as_str())?)
+    /// reads the config at `path`, applies `f` and saves the result, holding [Config::lock_at] throughout so changes made elsewhere aren't lost.
+    /// nothing is saved if `f` fails.
+    /// # Returns
+    /// whatever `f` returns.
+    fn update_at<R, P: AsRef<Path>>(path: P, f: impl FnOnce(&mut Self) -> Result<R,Error>) -> Result<R,Error> {
+        let path = path.as_ref();
+        let _lock = Self::lock_at(path)?;
+        let mut config = read_config::<Self>(path, true)?;
+        let ret = f(&mut config)?;
+        config.save_to(path)?;
+        Ok(ret)
+    }
+
+    fn update<R>(f: impl FnOnce(&mut Self) -> Result<R,Error>) -> Result<R,Error> {
+        Self::update_at(Self::file_path(), f)
+    }
+
+    /// like [Config::update], but starts from the default if there's no config saved yet.
+    fn update_or_default<R>(f: impl FnOnce(&mut Self) -> Result<R,Error>) -> Result<R,Error> where Self: Default {
+        let path = Self::file_path();
+        let _lock = Self::lock_at(&path)?;
+        let mut config = match path.is_file() {
+            true => read_config::<Self>(&path, true)?,
+            false => Self::default()
+        };
+        let ret = f(&mut config)?;
+        config.save_to(&path)?;
+        Ok(ret)
+    }
+
+    /// reads the config at `path`, migrating it first and saving the result if it's from an older version.
+    /// the migration is done holding [Config::lock_at], so it can't overwrite an update made meanwhile.
+    /// # Returns
+    /// [ConfigError::Corrupt] if it can't be parsed.
+    fn read_from<P: AsRef<Path>>(path: P) -> Result<Self,Error> {
+        read_config(path.as_ref(), false)
     }
 
     fn read() -> Result<Self,Error> {
-        Self::_read(Self::file_path())
+        Self::read_from(Self::file_path())
     }
 } //TODO save on drop - set a unsaved bool for changes (either wrap all mut fn's or just flag if get mut ref), panic in drop
 
@@ -77,51 +433,266 @@ impl Config for CACDownloadManifest {
     }
 }
 
+/// cached file digests keyed by absolute folder path, see [crate::dirhash::hash_directory_cached].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HashCache(pub HashMap<String,FolderCache>);
+
+impl Config for HashCache {
+    fn file_path() -> PathBuf {
+        HASH_CACHE_FILE.to_path_buf()
+    }
+}
+
+impl Config for ModsManifest {
+    fn file_path() -> PathBuf {
+        MODS_MANIFEST_FILE.to_path_buf()
+    }
+}
+
+impl CACDownloadManifest {
+    /// whether the temp file `fname` was downloaded from the same item and version, so can be resumed.
+    pub fn can_resume(&self, fname: &str, id: &TmpDownloadID) -> bool {
+        self.0.get(fname) == Some(id)
+    }
+
+    /// deletes a finished temp download and forgets it.
+    pub fn remove(&mut self, path: &Path) -> Result<(),Error> {
+        if path.exists() {
+            remove_path(path)?;
+        }
+        if let Some(fname) = path.file_name() {
+            self.0.remove(fname.to_string_lossy().as_ref());
+        }
+        Ok(())
+    }
+
+    /// deletes orphaned partial downloads in `folder` that have no entry, and forgets entries whose file is gone.
+    pub fn clean(&mut self, folder: &Path) -> Result<(),Error> {
+        if folder.is_dir() {
+            for e in std::fs::read_dir(folder)? {
+                let e = e?;
+                if !self.0.contains_key(e.file_name().to_string_lossy().as_ref()) {
+                    warn!("removing orphaned temp download '{}'",e.path().display());
+                    remove_path(&e.path())?;
+                }
+            }
+        }
+        self.0.retain(|fname,_| folder.join(fname).exists());
+        Ok(())
+    }
+}
+
+/// number of transfers run at once when updating if not set in the config.
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 3;
+
+fn default_max_concurrent_downloads() -> usize {
+    DEFAULT_MAX_CONCURRENT_DOWNLOADS
+}
+
 //TODO: remove mods from pending updates if they dont exist in content.json anymore (if client missed update and then it was removed from the server)
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// configs from before versioning, the fields added since all have defaults.
+fn config_v0_to_v1(_: &mut Map<String,Value>) -> Result<(),Error> {
+    Ok(())
+}
+
+/// moves the single user's settings into a profile named [DEFAULT_PROFILE].
+fn config_v1_to_v2(config: &mut Map<String,Value>) -> Result<(),Error> {
+    let mut profile = Map::new();
+    for key in ["username", "serverPassword", "enabledOptionals"] {
+        if let Some(v) = config.remove(key) {
+            profile.insert(key.into(), v);
+        }
+    }
+    config.insert("profiles".into(), Value::Object(Map::from_iter([(DEFAULT_PROFILE.to_string(), Value::Object(profile))])));
+    config.insert("activeProfile".into(), DEFAULT_PROFILE.into());
+    Ok(())
+}
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// the settings for one person or character using the launcher.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
-pub struct CACConfig {
+pub struct Profile {
     pub username: String,
-    pub arma_path: String,
     //shared between all servers that need it. TODO add to servers.json if a server requires a password (Option<bool> with default?)
+    #[serde(default)]
     pub server_password: String,
-    pub optionals_on: bool,
+    #[serde(default)]
     pub enabled_optionals: HashSet<String>,
+}
+
+impl Profile {
+    pub fn new(username: String) -> Self {
+        Self { username, server_password: String::new(), enabled_optionals: HashSet::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CACConfig {
+    /// how many of [CACConfig::MIGRATIONS] have been applied.
+    #[serde(default)]
+    pub version: u32,
+    pub arma_path: String,
+    pub optionals_on: bool,
+    /// profiles by name, see [CACConfig::profile].
+    pub profiles: BTreeMap<String,Profile>,
+    pub active_profile: String,
     pub pending_updates: HashSet<String>,
-    mod_dir: String //access via absolute_mod_dir instead 
+    /// mods that didn't match the published manifest when last verified, until they're updated.
+    #[serde(default)]
+    pub failed_verification: HashSet<String>,
+    #[serde(default = "default_max_concurrent_downloads")]
+    pub max_concurrent_downloads: usize,
+    #[serde(default)]
+    pub download_retry: RetryPolicy,
+    mod_dir: String, //access via absolute_mod_dir instead 
+    /// fields this version doesn't know, e.g. from a newer launcher. kept so saving doesn't drop them.
+    #[serde(flatten)]
+    pub extra: Map<String,Value>,
 }
 
 impl Config for CACConfig {
     fn file_path() -> PathBuf {
         CONFIG_FILE.to_path_buf()
     }
+
+    const MIGRATIONS: &'static [Migration] = &[config_v0_to_v1, config_v1_to_v2];
+    const BACKUPS: usize = 3;
 }
 
 impl CACConfig {
+    /// # Returns
+    /// the profile in use.
+    pub fn profile(&self) -> Result<&Profile,Error> {
+        self.profiles.get(&self.active_profile).ok_or(anyhow!("active profile '{}' does not exist",self.active_profile))
+    }
+
+    pub fn profile_mut(&mut self) -> Result<&mut Profile,Error> {
+        self.profiles.get_mut(&self.active_profile).ok_or(anyhow!("active profile '{}' does not exist",self.active_profile))
+    }
+
+    fn check_profile_name(&self, name: &str) -> Result<(),Error> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("profile name can't be empty"));
+        }
+        if self.profiles.contains_key(name) {
+            return Err(anyhow!("profile '{}' already exists",name));
+        }
+        Ok(())
+    }
+
+    /// adds an empty profile called `name`, using it as the username too.
+    pub fn add_profile(&mut self, name: &str) -> Result<(),Error> {
+        self.check_profile_name(name)?;
+        self.profiles.insert(name.to_string(), Profile::new(name.to_string()));
+        Ok(())
+    }
+
+    pub fn rename_profile(&mut self, old: &str, new: &str) -> Result<(),Error> {
+        self.check_profile_name(new)?;
+        let profile = self.profiles.remove(old).ok_or(anyhow!("profile '{}' does not exist",old))?;
+        self.profiles.insert(new.to_string(), profile);
+        if self.active_profile == old {
+            self.active_profile = new.to_string();
+        }
+        Ok(())
+    }
+
+    /// deletes the profile `name`. the last profile can't be deleted, if `name` is active the first remaining one is used instead.
+    pub fn remove_profile(&mut self, name: &str) -> Result<(),Error> {
+        if !self.profiles.contains_key(name) {
+            return Err(anyhow!("profile '{}' does not exist",name));
+        }
+        if self.profiles.len() == 1 {
+            return Err(anyhow!("can't delete the only profile"));
+        }
+        self.profiles.remove(name);
+        if self.active_profile == name {
+            self.active_profile = self.profiles.keys().next().unwrap().clone();
+        }
+        Ok(())
+    }
+
+    pub fn switch_profile(&mut self, name: &str) -> Result<(),Error> {
+        if !self.profiles.contains_key(name) {
+            return Err(anyhow!("profile '{}' does not exist",name));
+        }
+        self.active_profile = name.to_string();
+        Ok(())
+    }
+
+    /// sets the username of the active profile. it's passed to arma as `-name="..."` so it can't contain quotes.
+    pub fn set_username(&mut self, name: &str) -> Result<(),Error> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("username can't be empty"));
+        }
+        if name.contains('"') {
+            return Err(anyhow!("username can't contain '\"'"));
+        }
+        self.profile_mut()?.username = name.to_string();
+        Ok(())
+    }
+
+    /// sets the exile server password of the active profile, empty clears it.
+    pub fn set_server_password(&mut self, password: &str) -> Result<(),Error> {
+        if password.contains('"') {
+            return Err(anyhow!("password can't contain '\"'"));
+        }
+        self.profile_mut()?.server_password = password.to_string();
+        Ok(())
+    }
+
+    /// # Returns
+    /// the mods folder as stored, may be relative to the arma folder.
+    pub fn mod_dir(&self) -> &str {
+        &self.mod_dir
+    }
+
+    /// sets the mods folder. the folder doesn't have to exist yet but can't be a file.
+    /// `;` separates mods in arma's `-mod=` argument so it isn't allowed.
+    pub fn set_mod_dir(&mut self, dir: &str) -> Result<(),Error> {
+        let dir = dir.trim();
+        if dir.is_empty() {
+            return Err(anyhow!("mods directory can't be empty"));
+        }
+        if dir.contains('"') || dir.contains(';') {
+            return Err(anyhow!("mods directory can't contain '\"' or ';'"));
+        }
+        let old = std::mem::replace(&mut self.mod_dir, dir.to_string());
+        match self.absolute_mod_dir() {
+            Ok(p) if p.is_file() => {
+                self.mod_dir = old;
+                Err(anyhow!("'{}' is a file",p.display()))
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.mod_dir = old;
+                Err(e)
+            }
+        }
+    }
+
+    /// # Returns
+    /// the arma install folder, which `arma_path` points to the exe in.
+    pub fn arma_dir(&self) -> Result<PathBuf,Error> {
+        PathBuf::from(&self.arma_path).parent().map(|p| p.to_path_buf())
+        .ok_or(anyhow!("arma path '{}' has no parent folder",self.arma_path))
+    }
+
     pub fn absolute_mod_dir(&self) -> Result<PathBuf,Error> {
         //arma will crash if moddir contains relative e.g. "./" ("Mods/ is fine"), so resolve if is the case
         //dont store the absolute path though, then can move folders around without stuff breaking
-        
-        //windows paths only
-        let starts_drive: bool = !cfg!(windows) || {
-            let (b,e) = self.mod_dir.split_at(0);
-            e.starts_with(":/") || e.starts_with(";\\")
-        };
-
-        if self.mod_dir.starts_with("./") || 
-        self.mod_dir.starts_with(".\\") || 
-        self.mod_dir.starts_with("../") || 
-        self.mod_dir.starts_with(".\\") || 
-        self.mod_dir.starts_with("\\") ||
-        self.mod_dir.starts_with("/") ||
-        starts_drive {
-            Ok(path::absolute(PathBuf::from(&self.mod_dir).parent().unwrap())
-            .map_err(|_| anyhow!("failed to get absolute path of config.mod_dir"))?.into())
-        }else {
+        let dir = PathBuf::from(&self.mod_dir);
+        let dir = match dir.is_absolute() {
+            true => dir,
             //arma folder is at parent of ...exe
-            Ok(PathBuf::from(&self.arma_path).parent().unwrap().join(&self.mod_dir))
-        }
+            false => self.arma_dir()?.join(dir)
+        };
+        Ok(path::absolute(dir).map_err(|_| anyhow!("failed to get absolute path of config.mod_dir"))?)
     }
 }
 
@@ -180,15 +751,24 @@ impl CACConfig {
 
         //TODO: prompt for abs/rel mod directory with default rel "Mods/"
 
-        Ok(CACConfig {
+        Ok(Self::with_arma_path(ap))
+    }
+
+    /// the defaults for an arma install at `ap`, the path to its exe.
+    pub fn with_arma_path(ap: String) -> Self {
+        CACConfig {
+            version: Self::MIGRATIONS.len() as u32,
             arma_path: ap.clone(),
-            username: whoami::username(),
-            server_password: String::new(),
-            enabled_optionals: HashSet::new(),
             optionals_on: false,
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Profile::new(whoami::username()))]),
+            active_profile: DEFAULT_PROFILE.to_string(),
             pending_updates: HashSet::new(),
-            mod_dir: PathBuf::from(ap).parent().unwrap().join("Mods").to_str().unwrap().into()
-        })
+            failed_verification: HashSet::new(),
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            download_retry: RetryPolicy::default(),
+            mod_dir: PathBuf::from(ap).parent().unwrap().join("Mods").to_str().unwrap().into(),
+            extra: Map::new(),
+        }
     }
 }
 
@@ -230,9 +810,10 @@ impl<'a> Iterator for LinksIter<'a> {
 
 #[derive(Serialize, Deserialize,Debug,Clone)]
 pub struct DLC {
-    link: Links,
-    pwd: String,
-    description: String
+    pub link: Links,
+    /// password the dlc archive is encrypted with.
+    pub pwd: String,
+    pub description: String
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone,Default)]
@@ -248,14 +829,12 @@ impl Config for CACContent {
     fn file_path() -> PathBuf {
         CONTENT_FILE.to_path_buf()
     }
+
+    const BACKUPS: usize = 3;
 }
 
 impl CACContent {
 
-    pub fn read_from(path: PathBuf) -> Result<Self,Error> {
-        Self::_read(path)
-    }
-
     /// # Returns: combined iterator over all content items in the manifest. 
     pub fn content_iter<'a>(&'a self) -> impl Iterator<Item = (&'a String,&'a Links)> {
         self.dlc.iter().map(|x| (x.0,&x.1.link)).chain(self.mods.iter().chain(self.optionals.iter())).into_iter()
@@ -1,24 +1,34 @@
 use anyhow::{anyhow, Error};
 use size::Size;
 use core::hash;
-use jwalk::WalkDir;
+use jwalk::{WalkDir, WalkDirGeneric};
 use memmap2::Mmap;
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::{
+    collections::{BTreeMap, HashMap},
     fs::{self, File},
     hash::Hasher,
-    path::Path,
-    sync::Mutex,
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicU64, Ordering}, Mutex},
+    time::SystemTime,
 };
+use crate::{PROGRESS_STYLE_HASH, configs::{CACConfig, CACContent, Config, HashCache, HASH_CACHE_FILE, MODS_MANIFEST_FILE}};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use tokio_util::sync::CancellationToken;
 use stopwatch::Stopwatch;
 use xxhash_rust::xxh3::{self, xxh3_128, xxh3_64, Xxh3};
 
-//TODO: progress bar
-//TODO: symlink
+/// files are hashed in chunks this big, so progress and cancellation don't wait on a whole large file.
+const HASH_CHUNK_SIZE: usize = 16 * 1024 * 1024;
 
-pub fn build_dir_manifest(base: &Path, manifestPath: &Path) -> Result<(), Error> {
+/// builds a [ModManifest] for each folder in `base` and writes them to `manifestPath` as a [ModsManifest].
+/// links are handled according to `policy`, which is recorded in each manifest so verifying does the same.
+/// `blob_url` is recorded as each mod's [ModManifest::blob_url], the files have to be uploaded there separately.
+/// unchanged files reuse their digest from `cache`, `force` rehashes everything.
+pub fn build_dir_manifest(base: &Path, manifestPath: &Path, policy: LinkPolicy, blob_url: Option<&str>, cache: &mut HashCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<(), Error> {
 
     if(!fs::exists(base)?) {
         return Err(anyhow!("path '{}' does not exist",{base.as_os_str().to_str().ok_or(anyhow!("failed to convert &OsStr to &Str"))?})); 
@@ -26,33 +36,18 @@ pub fn build_dir_manifest(base: &Path, manifestPath: &Path) -> Result<(), Error>
 
     let mut clock = Stopwatch::start_new();
 
-    let mut manifest = serde_json::Map::new();
-
-    let mut entries = WalkDir::new(base).min_depth(1).max_depth(1).into_iter();
-
-    while let Some(e) = entries.next() {
+    let mut folders = Vec::new();
+    for e in fs::read_dir(base)? {
         let entry = e?;
-        if (entry.file_type().is_dir()) {
-            println!(
-                "hashing {}",
-                entry
-                    .path()
-                    .as_os_str()
-                    .to_str()
-                    .ok_or(anyhow!("failed to convert &OsStr to &Str"))?
-            ); //this is excessive...
-            let dpbuf = entry.path();
-            let dp = dpbuf.as_path();
-            manifest.insert(
-                dp.strip_prefix(base)?
-                    .to_path_buf()
-                    .into_os_string()
-                    .into_string()
-                    .map_err(|_| anyhow!("failed to convert OsString to String"))?,
-                hash_directory(dp)?.to_string().into(),
-            ); //json only supports 64bit ints
+        if entry.file_type()?.is_dir() {
+            let name = entry.file_name().into_string().map_err(|_| anyhow!("failed to convert OsString to String"))?;
+            folders.push((name, entry.path(), policy));
         }
     }
+    let mut manifest = ModsManifest(build_manifests(folders, cache, force, progress, cancel)?);
+    for m in manifest.0.values_mut() {
+        m.blob_url = blob_url.map(str::to_string);
+    }
 
     let mut file = std::fs::OpenOptions::new()
         .create(true)
@@ -61,65 +56,508 @@ pub fn build_dir_manifest(base: &Path, manifestPath: &Path) -> Result<(), Error>
         .open(manifestPath)?;
     let mut writer = std::io::BufWriter::new(file);
 
-    let mv = serde_json::Value::Object(manifest);
-    serde_json::to_writer_pretty(writer, &mv)?;
+    serde_json::to_writer_pretty(writer, &manifest)?;
 
     clock.stop();
-    println!("manifest built in {}s", clock.elapsed().as_secs());
+    info!("manifest for {} built in {}s", base.display(), clock.elapsed().as_secs());
 
     return Ok(());
 }
 
-pub fn hash_directory(base_path: &Path) -> Result<u128, Error> {
-    let mut hashTable: Mutex<Vec<u128>> = Mutex::new(Vec::new());
-    let _ = WalkDir::new(base_path)
-        .sort(false)//par-bridge discards iterator order.
+#[derive(thiserror::Error, Debug)]
+pub enum DirHashError {
+    #[error("hashing cancelled")]
+    Cancelled,
+    #[error("'{0}' is a link, which the link policy doesn't allow")]
+    LinkRejected(PathBuf),
+    #[error("link '{link}' loops back to '{ancestor}'")]
+    LinkCycle { link: PathBuf, ancestor: PathBuf },
+    #[error("'{0}' is not a file, folder or link")]
+    SpecialFile(PathBuf),
+}
+
+/// what to do with symlinks and junctions found while hashing a folder.
+/// the folder being hashed is always followed, so a whole mod can be junctioned to another drive under any policy.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkPolicy {
+    /// hash what the link points at as if it were in the folder.
+    Follow,
+    /// hash the link's target path, not the contents.
+    #[default]
+    HashAsLink,
+    /// fail with [DirHashError::LinkRejected].
+    Reject,
+}
+
+/// progress of a hashing pass in files and bytes, shared between the threads hashing.
+/// cached files count as done straight away. also drives `bar` if given.
+#[derive(Debug, Default)]
+pub struct HashProgress {
+    files_total: AtomicU64,
+    files_done: AtomicU64,
+    bytes_total: AtomicU64,
+    bytes_done: AtomicU64,
+    bar: Option<ProgressBar>,
+}
+
+impl HashProgress {
+    pub fn new(bar: Option<ProgressBar>) -> Self {
+        if let Some(b) = &bar {
+            b.set_style(ProgressStyle::with_template(PROGRESS_STYLE_HASH).unwrap());
+            b.set_length(0);
+            b.set_position(0);
+        }
+        Self { bar, ..Default::default() }
+    }
+
+    /// # Returns
+    /// (done, total) files.
+    pub fn files(&self) -> (u64, u64) {
+        (self.files_done.load(Ordering::Relaxed), self.files_total.load(Ordering::Relaxed))
+    }
+
+    /// # Returns
+    /// (done, total) bytes.
+    pub fn bytes(&self) -> (u64, u64) {
+        (self.bytes_done.load(Ordering::Relaxed), self.bytes_total.load(Ordering::Relaxed))
+    }
+
+    fn add_total(&self, entries: &[ScannedEntry]) {
+        let files = entries.iter().filter(|e| matches!(e.kind, EntryKind::File));
+        self.files_total.fetch_add(files.clone().count() as u64, Ordering::Relaxed);
+        self.bytes_total.fetch_add(files.map(|e| e.size).sum(), Ordering::Relaxed);
+        if let Some(b) = &self.bar {
+            b.set_length(self.bytes_total.load(Ordering::Relaxed));
+        }
+        self.update_message();
+    }
+
+    fn add_bytes(&self, n: u64) {
+        self.bytes_done.fetch_add(n, Ordering::Relaxed);
+        if let Some(b) = &self.bar {
+            b.inc(n);
+        }
+    }
+
+    fn file_done(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.update_message();
+    }
+
+    fn update_message(&self) {
+        if let Some(b) = &self.bar {
+            let (done, total) = self.files();
+            b.set_message(format!("{}/{} files", done, total));
+        }
+    }
+}
+
+enum EntryKind {
+    File,
+    Dir,
+    /// a link that isn't followed, with its target.
+    Link(PathBuf),
+}
+
+/// an entry found under a folder being hashed, collected before hashing so the totals are known.
+struct ScannedEntry {
+    path: PathBuf,
+    rel_path: PathBuf,
+    kind: EntryKind,
+    size: u64,
+    mtime: Option<SystemTime>,
+}
+
+/// checks whether following the directory link `link`, found in `dir`, would lead back to a folder it's already inside.
+/// jwalk only catches links whose target is spelled exactly like the ancestor, so compare canonical paths instead.
+/// # Returns
+/// the ancestor of `dir`, up to `root`, that `link` points at.
+fn link_cycle(root: &Path, dir: &Path, link: &Path) -> Option<PathBuf> {
+    let target = fs::canonicalize(link).ok()?;
+    dir.ancestors()
+        .take_while(|a| a.starts_with(root))
+        .find(|a| fs::canonicalize(a).is_ok_and(|a| a == target))
+        .map(Path::to_path_buf)
+}
+
+/// lists everything under `base`, handling links according to `policy`.
+fn scan(base: &Path, policy: LinkPolicy) -> Result<Vec<ScannedEntry>, Error> {
+    let root = base.to_path_buf();
+    //client state marks links that would loop, and stops them being read
+    WalkDirGeneric::<((), Option<PathBuf>)>::new(base)
+        .sort(false)
+        .follow_links(policy == LinkPolicy::Follow)
+        .process_read_dir(move |_, dir, _, children| {
+            for child in children.iter_mut().flatten() {
+                if child.path_is_symlink() && child.file_type.is_dir() {
+                    if let Some(ancestor) = link_cycle(&root, dir, &child.path()) {
+                        child.read_children_path = None;
+                        child.client_state = Some(ancestor);
+                    }
+                }
+            }
+        })
         .into_iter()
-        .par_bridge()
-        .try_for_each(|e| -> Result<(), Error> {
-            let entry = e?;
+        .map(|e| -> Result<ScannedEntry, Error> {
+            let entry = e.map_err(|e| -> Error {
+                match (e.path(), e.loop_ancestor()) {
+                    (Some(link), Some(ancestor)) => DirHashError::LinkCycle { link: link.to_path_buf(), ancestor: ancestor.to_path_buf() }.into(),
+                    _ => e.into()
+                }
+            })?;
             let path = entry.path();
-            let rel_path = path.strip_prefix(base_path)?;
+            if let Some(ancestor) = &entry.client_state {
+                return Err(DirHashError::LinkCycle { link: path, ancestor: ancestor.clone() }.into());
+            }
+            let rel_path = path.strip_prefix(base)?.to_path_buf();
 
-            let mut hasher = Xxh3::new();
-            hasher.update(rel_path.as_os_str().as_encoded_bytes());
-
-            let ftype = entry.file_type();
-            if ftype.is_file() {
-
-                //TODO RM
-                let fsize = Size::from_bytes(entry.metadata()?.len());
-                println!("{}, size: {}",path.as_os_str().to_str().unwrap(),fsize.format()); 
-
-                hasher.write_u8(0);
-
-                //hash file contents
-                let mut file = File::open(path)?;
-                let mmap = unsafe { Mmap::map(&file)? };
-                hasher.update(&mmap);
-            } else if ftype.is_dir() {
-                hasher.write_u8(1);
-            } else if ftype.is_symlink() {
-                hasher.write_u8(2);
-                let dest = fs::read_link(path)?;
-                hasher.update(dest.as_os_str().as_encoded_bytes());
-            } else {
-                //skip
+            let is_link = entry.depth() > 0 && entry.path_is_symlink();
+            let (kind, size, mtime) = match policy {
+                LinkPolicy::Reject if is_link => return Err(DirHashError::LinkRejected(path).into()),
+                LinkPolicy::HashAsLink if is_link => (EntryKind::Link(fs::read_link(&path)?), 0, Some(fs::symlink_metadata(&path)?.modified()?)),
+                _ if entry.file_type().is_file() => {
+                    let meta = entry.metadata()?;
+                    (EntryKind::File, meta.len(), Some(meta.modified()?))
+                }
+                _ if entry.file_type().is_dir() => (EntryKind::Dir, 0, None),
+                _ => return Err(DirHashError::SpecialFile(path).into())
+            };
+            Ok(ScannedEntry { path, rel_path, kind, size, mtime })
+        })
+        .collect()
+}
+
+/// scans every folder first so `progress` has the totals, then builds each one's manifest.
+fn build_manifests(folders: Vec<(String, PathBuf, LinkPolicy)>, cache: &mut HashCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<BTreeMap<String, ModManifest>, Error> {
+    let mut scanned = Vec::with_capacity(folders.len());
+    for (name, path, policy) in folders {
+        let entries = scan(&path, policy)?;
+        progress.add_total(&entries);
+        scanned.push((name, path, policy, entries));
+    }
+
+    let mut ret = BTreeMap::new();
+    for (name, path, policy, entries) in scanned {
+        let folder_cache = cache.0.entry(std::path::absolute(&path)?.to_string_lossy().to_string()).or_default();
+        hash_scanned(entries, folder_cache, force, progress, cancel)?;
+        ret.insert(name, ModManifest::from_cache(folder_cache, policy));
+    }
+    Ok(ret)
+}
+
+/// a file's contents digest from a previous pass, reused while its size and mtime are unchanged.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CachedHash {
+    pub size: u64,
+    pub mtime: SystemTime,
+    /// xxh3 128 of the contents as hex, json only supports 64bit ints.
+    pub hash: String,
+    /// target of a link hashed with [LinkPolicy::HashAsLink], `hash` is then of the target path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// cached digests for one folder, keyed by path relative to it.
+pub type FolderCache = HashMap<String, CachedHash>;
+
+/// xxh3 128 of a file's contents.
+pub fn hash_file(path: &Path) -> Result<u128, Error> {
+    hash_file_with(path, &HashProgress::default(), &CancellationToken::new())
+}
+
+/// [hash_file] in chunks, so progress is reported and cancellation is checked within large files.
+fn hash_file_with(path: &Path, progress: &HashProgress, cancel: &CancellationToken) -> Result<u128, Error> {
+    let file = File::open(path)?;
+    //mapping an empty file fails on windows
+    if file.metadata()?.len() == 0 {
+        return Ok(xxh3_128(&[]));
+    }
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut hasher = Xxh3::new();
+    for chunk in mmap.chunks(HASH_CHUNK_SIZE) {
+        if cancel.is_cancelled() {
+            return Err(DirHashError::Cancelled.into());
+        }
+        hasher.update(chunk);
+        progress.add_bytes(chunk.len() as u64);
+    }
+    Ok(hasher.digest128())
+}
+
+/// hashes `base_path` with [LinkPolicy::HashAsLink].
+pub fn hash_directory(base_path: &Path) -> Result<u128, Error> {
+    hash_directory_cached(base_path, LinkPolicy::HashAsLink, &mut FolderCache::new(), true, &HashProgress::default(), &CancellationToken::new())
+}
+
+/// same as [hash_directory], but files whose size and mtime match their entry in `cache` aren't read again.
+/// `cache` is updated with the files hashed this time, and entries for files that are gone are dropped.
+/// if cancelled, the files hashed so far are still added to `cache`.
+/// `force` ignores the cache and rehashes everything.
+/// # Returns
+/// [DirHashError::Cancelled] if `cancel` is triggered part way through,
+/// [DirHashError::LinkRejected] or [DirHashError::LinkCycle] if a link can't be handled under `policy`.
+pub fn hash_directory_cached(base_path: &Path, policy: LinkPolicy, cache: &mut FolderCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<u128, Error> {
+    let entries = scan(base_path, policy)?;
+    progress.add_total(&entries);
+    hash_scanned(entries, cache, force, progress, cancel)
+}
+
+fn hash_scanned(entries: Vec<ScannedEntry>, cache: &mut FolderCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<u128, Error> {
+    let seen: Mutex<FolderCache> = Mutex::new(FolderCache::new());
+    let old: &FolderCache = cache;
+    let hashTable = entries
+        .par_iter()
+        .map(|entry| -> Result<u128, Error> {
+            if cancel.is_cancelled() {
+                return Err(DirHashError::Cancelled.into());
             }
 
-            let mut lock = hashTable.lock().unwrap();
-            lock.push(hasher.digest128());
-            return Ok(());
-        });
+            let mut hasher = Xxh3::new();
+            hasher.update(entry.rel_path.as_os_str().as_encoded_bytes());
+
+            let key = entry.rel_path.to_string_lossy().replace('\\', "/");
+            match &entry.kind {
+                EntryKind::File => {
+                    hasher.write_u8(0);
 
-    let mut lock = hashTable.lock().unwrap();
+                    let (size, mtime) = (entry.size, entry.mtime.unwrap());
+                    let hash = match old.get(&key) {
+                        Some(c) if !force && c.link.is_none() && c.size == size && c.mtime == mtime => {
+                            progress.add_bytes(size);
+                            c.hash.clone()
+                        }
+                        _ => format!("{:032x}", hash_file_with(&entry.path, progress, cancel)?)
+                    };
+                    progress.file_done();
+                    hasher.update(hash.as_bytes());
+                    seen.lock().unwrap().insert(key, CachedHash { size, mtime, hash, link: None });
+                }
+                EntryKind::Dir => hasher.write_u8(1),
+                EntryKind::Link(dest) => {
+                    hasher.write_u8(2);
+                    hasher.update(dest.as_os_str().as_encoded_bytes());
+                    let hash = format!("{:032x}", xxh3_128(dest.as_os_str().as_encoded_bytes()));
+                    let link = Some(dest.to_string_lossy().to_string());
+                    seen.lock().unwrap().insert(key, CachedHash { size: 0, mtime: entry.mtime.unwrap(), hash, link });
+                }
+            }
+            Ok(hasher.digest128())
+        })
+        .collect::<Result<Vec<u128>, Error>>();
+    let mut hashTable = match hashTable {
+        Ok(t) => {
+            *cache = seen.into_inner().unwrap();
+            t
+        }
+        //keep what was hashed before stopping so it isn't done again. stale entries go on the next full run
+        Err(e) => {
+            cache.extend(seen.into_inner().unwrap());
+            return Err(e);
+        }
+    };
 
     //sort results so the table order is deterministic / same order for same folder contents.
-    lock.sort();
+    hashTable.sort();
 
     let mut hasher = xxhash_rust::xxh3::Xxh3Default::new();
-    for hash in lock.iter() {
+    for hash in hashTable.iter() {
         hasher.update(&hash.to_le_bytes());
     }
     return Ok(hasher.digest128());
 }
+
+/// one file in a [ModManifest].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+    pub size: u64,
+    /// xxh3 128 of the contents as hex.
+    pub hash: String,
+    /// set if this is a link rather than a file, see [LinkPolicy::HashAsLink].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// every file in a mod folder, keyed by path relative to it with `/` separators.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModManifest {
+    /// how links were handled when this was built, verifying uses the same.
+    #[serde(default)]
+    pub link_policy: LinkPolicy,
+    /// where each file can be downloaded on its own, as `{blobUrl}/{hash}`. lets updates fetch only the files that changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_url: Option<String>,
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+/// manifests for each mod in a folder keyed by the mod's folder name, as published next to content.json.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModsManifest(pub BTreeMap<String, ModManifest>);
+
+/// how an installed mod differs from its manifest, as paths relative to the mod folder.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    /// files on disk that aren't in the manifest.
+    pub added: Vec<String>,
+    /// files in the manifest that are missing on disk.
+    pub removed: Vec<String>,
+    /// files whose size or contents differ.
+    pub modified: Vec<String>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// files that have to be fetched again to fix the mod.
+    pub fn needed(&self) -> impl Iterator<Item = &String> {
+        self.removed.iter().chain(self.modified.iter())
+    }
+
+    /// a line per differing file, listing at most `limit` of them.
+    pub fn report(&self, limit: usize) -> Vec<String> {
+        let all: Vec<String> = self.modified.iter().map(|f| format!("modified: {}", f))
+            .chain(self.removed.iter().map(|f| format!("missing: {}", f)))
+            .chain(self.added.iter().map(|f| format!("extra: {}", f)))
+            .collect();
+        let mut ret: Vec<String> = all.iter().take(limit).cloned().collect();
+        if all.len() > limit {
+            ret.push(format!("...and {} more", all.len() - limit));
+        }
+        ret
+    }
+}
+
+/// joins a manifest path onto `base`.
+pub fn manifest_path(base: &Path, rel: &str) -> PathBuf {
+    let mut p = base.to_path_buf();
+    p.extend(rel.split('/'));
+    p
+}
+
+impl ModManifest {
+    /// hashes every file in `base`, reusing digests from `cache` like [hash_directory_cached].
+    pub fn build(base: &Path, policy: LinkPolicy, cache: &mut FolderCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<Self, Error> {
+        hash_directory_cached(base, policy, cache, force, progress, cancel)?;
+        Ok(Self::from_cache(cache, policy))
+    }
+
+    /// the files of a folder whose `cache` was just updated by hashing it.
+    fn from_cache(cache: &FolderCache, link_policy: LinkPolicy) -> Self {
+        Self {
+            link_policy,
+            blob_url: None,
+            files: cache.iter().map(|(k, c)| (k.clone(), FileEntry { size: c.size, hash: c.hash.clone(), link: c.link.clone() })).collect()
+        }
+    }
+
+    /// compares `installed` against this manifest.
+    pub fn diff(&self, installed: &ModManifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+        for (path, entry) in &self.files {
+            match installed.files.get(path) {
+                None => diff.removed.push(path.clone()),
+                Some(e) if e != entry => diff.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.added = installed.files.keys().filter(|k| !self.files.contains_key(*k)).cloned().collect();
+        diff
+    }
+
+    /// the url to download the file at `rel` on its own from, see [ModManifest::blob_url].
+    pub fn blob(&self, rel: &str) -> Option<String> {
+        let entry = self.files.get(rel)?;
+        Some(format!("{}/{}", self.blob_url.as_ref()?.trim_end_matches('/'), entry.hash))
+    }
+
+    /// fixes the mod at `target` using the files in `source`, a fresh copy of the mod.
+    /// only the files in `diff` are touched: missing and modified ones are copied over and extra ones are deleted.
+    /// copied files are checked against this manifest first, so a bad source can't make things worse.
+    pub fn repair(&self, target: &Path, source: &Path, diff: &ManifestDiff) -> Result<(), Error> {
+        for rel in diff.needed() {
+            let expected = self.files.get(rel).ok_or(anyhow!("'{}' is not in the manifest", rel))?;
+            if expected.link.is_some() {
+                return Err(anyhow!("'{}' should be a link, which can't be repaired", rel));
+            }
+            let from = manifest_path(source, rel);
+            if !from.is_file() || format!("{:032x}", hash_file(&from)?) != expected.hash {
+                return Err(anyhow!("the new copy of '{}' doesn't match the manifest", rel));
+            }
+            let to = manifest_path(target, rel);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to)?;
+        }
+        for rel in &diff.added {
+            let path = manifest_path(target, rel);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// checks each installed mod in `names` against `published`.
+/// each mod is hashed with the link policy from its published manifest.
+/// mods that aren't installed or have no published manifest are skipped.
+/// # Returns
+/// the differences for each mod checked, empty if it matches.
+pub fn verify_mods(mod_dir: &Path, published: &ModsManifest, names: &[String], cache: &mut HashCache, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<BTreeMap<String, ManifestDiff>, Error> {
+    let mut folders = Vec::new();
+    for name in names {
+        let path = mod_dir.join(name);
+        match published.0.get(name) {
+            None => warn!("no published manifest for {}, skipping verification", name),
+            Some(m) if path.is_dir() => folders.push((name.clone(), path, m.link_policy)),
+            Some(_) => {}
+        }
+    }
+
+    let installed = build_manifests(folders, cache, force, progress, cancel)?;
+    Ok(installed.into_iter().map(|(name, m)| {
+        let diff = published.0[&name].diff(&m);
+        (name, diff)
+    }).collect())
+}
+
+/// verifies the installed mods and optionals against the manifest published with the content manifest.
+/// mods that don't match are added to the saved config's `pending_updates` and `failed_verification`, and the hash cache is saved.
+/// the hash cache is saved even if cancelled, so the files already hashed don't need doing again.
+/// # Returns
+/// the differences for each mod checked, see [verify_mods].
+pub fn verify_installed_mods(config: &CACConfig, force: bool, progress: &HashProgress, cancel: &CancellationToken) -> Result<BTreeMap<String, ManifestDiff>, Error> {
+    if !MODS_MANIFEST_FILE.is_file() {
+        return Err(anyhow!("no published mod manifest at '{}', update the launcher config first", MODS_MANIFEST_FILE.display()));
+    }
+    let published = ModsManifest::read()?;
+    let content = CACContent::read()?;
+    let names: Vec<String> = content.mods.keys().chain(content.optionals.keys()).cloned().collect();
+    let mut cache = match HASH_CACHE_FILE.is_file() {
+        true => HashCache::read()?,
+        false => HashCache::default()
+    };
+
+    let ret = verify_mods(&config.absolute_mod_dir()?, &published, &names, &mut cache, force, progress, cancel);
+    HashCache::update_or_default(|c| {
+        c.0.extend(cache.0);
+        Ok(())
+    })?;
+    let ret = ret?;
+
+    let failed: Vec<String> = ret.iter().filter(|(_, d)| !d.is_empty()).map(|(name, diff)| {
+        warn!("{} failed verification: {:?}", name, diff);
+        name.clone()
+    }).collect();
+    CACConfig::update(|c| {
+        c.failed_verification.retain(|name| !ret.contains_key(name));
+        c.failed_verification.extend(failed.iter().cloned());
+        c.pending_updates.extend(failed);
+        Ok(())
+    })?;
+    Ok(ret)
+}
@@ -1,16 +1,17 @@
-use std::{cell::RefCell, fs::OpenOptions, io::{Seek, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+use std::{cell::RefCell, collections::{BTreeMap, HashMap, hash_map::RandomState}, fs::OpenOptions, hash::{BuildHasher, Hasher}, io::{Read, Seek, Write}, ops::{Deref, DerefMut}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
 
 use anyhow::{Error, anyhow};
 use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
-use futures_util::TryStreamExt;
+use futures_util::{StreamExt, TryStreamExt, future::try_join_all, stream};
 use indicatif::{ProgressBar, ProgressStyle};
-use log::warn;
+use log::{info, warn};
 use reqwest::{Client, Request, StatusCode, Url, header::{self, HeaderMap}};
+use serde::{Deserialize, Serialize};
 //use sha2::{Digest, Sha256, Sha512};
-use tokio::{io::AsyncReadExt, time::sleep};
+use tokio::{io::AsyncReadExt, sync::{OwnedSemaphorePermit, Semaphore}, task::JoinSet, time::sleep};
 use tokio_util::{io::StreamReader, sync::CancellationToken};
 
-use crate::{ClientCtx, PROGRESS_STYLE_DOWNLOAD, PROGRESS_STYLE_MESSAGE, TIMEOUT, configs::*, final_url, msgraph::{self, MsGraphError}, unzip};
+use crate::{ClientCtx, quickxor::QuickXorHash, PROGRESS_STYLE_DOWNLOAD, PROGRESS_STYLE_MESSAGE, TIMEOUT, configs::*, final_url, msgraph::{self, MsGraphError}, extract::{self, ExtractError}, dirhash::{self, DirHashError, HashProgress, ManifestDiff, ModManifest, ModsManifest}};
 
 //TODO replace remove_dir_all with this
 pub fn remove_path(path: &Path) -> std::io::Result<()> {
@@ -23,9 +24,311 @@ pub fn remove_path(path: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// copies a file or folder tree from `from` to `to`, following symlinks.
+fn copy_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::metadata(from)?.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// moves a file or folder, falling back to copy and delete when renaming fails e.g. across drives.
+/// a partial copy is removed again if copying fails.
+fn move_path(from: &Path, to: &Path) -> Result<(),Error> {
+    if let Err(e) = std::fs::rename(from, to) {
+        info!("rename of {} failed ({}), copying instead",from.display(),e);
+        if let Err(e) = copy_path(from, to) {
+            let _ = remove_path(to);
+            return Err(anyhow!("failed to copy '{}': {}",from.display(),e));
+        }
+        remove_path(from).map_err(|e| anyhow!("copied '{}' but failed to remove it: {}",from.display(),e))?;
+    }
+    Ok(())
+}
+
+/// moves the entries `names` in the folder `from` into the folder `to`, creating it if needed. missing entries are skipped.
+/// nothing is moved if any entry already exists in `to`. if a move fails, the entries already moved are moved back.
+/// # Returns
+/// the error, naming any entries that couldn't be moved back.
+pub fn move_entries(from: &Path, to: &Path, names: &[String]) -> Result<(),Error> {
+    let names: Vec<&String> = names.iter().filter(|n| from.join(n).exists()).collect();
+    if let Some(n) = names.iter().find(|n| to.join(n).exists()) {
+        return Err(anyhow!("'{}' already exists",to.join(n).display()));
+    }
+    std::fs::create_dir_all(to)?;
+    let mut moved = Vec::new();
+    for name in names {
+        let err = match move_path(&from.join(name), &to.join(name)) {
+            Ok(()) => {
+                moved.push(name);
+                continue;
+            }
+            Err(e) => e
+        };
+        //copied over but the original was only partly deleted, so put the copy back too
+        if to.join(name).exists() {
+            moved.push(name);
+        }
+        let stranded: Vec<&str> = moved.into_iter().filter(|n| {
+            let src = from.join(n);
+            let back = (!src.exists() || remove_path(&src).is_ok()) && move_path(&to.join(n), &src).is_ok();
+            if !back {
+                warn!("failed to move '{}' back to {}",n,from.display());
+            }
+            !back
+        }).map(|n| n.as_str()).collect();
+        return match stranded.is_empty() {
+            true => Err(err.context("moved everything back")),
+            false => Err(err.context(format!("these couldn't be moved back and are in '{}': {}",to.display(),stranded.join(", "))))
+        };
+    }
+    Ok(())
+}
+
+/// splits a header value into its `;` separated parameters, ignoring separators inside quoted strings.
+/// quoted values are unescaped. parameter names are lowercased.
+fn header_params(value: &str) -> Vec<(String, String)> {
+    let mut ret = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            name.push(c);
+        }
+        let mut val = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => val.extend(chars.next()),
+                        c => val.push(c)
+                    }
+                }
+            }
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                val.push(c);
+            }
+        }
+        if !name.trim().is_empty() {
+            ret.push((name.trim().to_lowercase(), val.trim().to_string()));
+        }
+        if chars.next().is_none() {
+            return ret;
+        }
+    }
+}
+
+/// decodes an [RFC 5987](https://www.rfc-editor.org/rfc/rfc5987) extended value e.g. `UTF-8''na%C3%AFve.7z`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_lowercase();
+    let _lang = parts.next()?;
+    let bytes = urlencoding::decode_binary(parts.next()?.as_bytes());
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes.into_owned()).ok(),
+        "iso-8859-1" => Some(bytes.iter().map(|b| *b as char).collect()),
+        _ => None
+    }
+}
+
+/// gets the filename from a [Content-Disposition](https://www.rfc-editor.org/rfc/rfc6266) header value,
+/// preferring `filename*` over `filename`. the name is not sanitised.
+pub fn content_disposition_filename(value: &str) -> Option<String> {
+    let params = header_params(value);
+    params.iter().find(|(k,_)| k == "filename*").and_then(|(_,v)| decode_ext_value(v))
+    .or_else(|| params.into_iter().find(|(k,_)| k == "filename").map(|(_,v)| v))
+    .filter(|v| !v.is_empty())
+}
+
+/// makes a downloaded filename safe to join onto a folder: only the last path component is kept,
+/// characters windows doesnt allow are replaced and reserved device names (CON, COM1...) are prefixed with '_'.
+pub fn sanitise_filename(name: &str) -> Result<String,Error> {
+    const RESERVED: [&str; 22] = ["CON","PRN","AUX","NUL","COM1","COM2","COM3","COM4","COM5","COM6","COM7","COM8","COM9","LPT1","LPT2","LPT3","LPT4","LPT5","LPT6","LPT7","LPT8","LPT9"];
+
+    let last = name.rsplit(['/','\\']).next().unwrap_or_default();
+    let cleaned: String = last.chars().map(|c| match c.is_control() || "<>:\"|?*".contains(c) {
+        true => '_',
+        false => c
+    }).collect();
+    //windows strips trailing dots and spaces
+    let cleaned = cleaned.trim().trim_end_matches(['.',' ']);
+    if cleaned.is_empty() {
+        return Err(anyhow!("unusable download filename '{}'",name));
+    }
+    let stem = cleaned.split('.').next().unwrap_or_default().trim_end().to_uppercase();
+    match RESERVED.contains(&stem.as_str()) {
+        true => Ok(format!("_{}",cleaned)),
+        false => Ok(cleaned.to_string())
+    }
+}
+
+/// the sanitised filename for a download response, from its Content-Disposition header or failing that the last segment of the url path.
+pub fn response_filename(headers: &HeaderMap, url: &Url) -> Result<String,Error> {
+    let name = headers.get(header::CONTENT_DISPOSITION)
+    .and_then(|v| content_disposition_filename(&String::from_utf8_lossy(v.as_bytes())))
+    .or_else(|| {
+        url.path_segments()?.filter(|s| !s.is_empty()).last()
+        .map(|s| urlencoding::decode(s).map(|s| s.into_owned()).unwrap_or(s.to_string()))
+    })
+    .ok_or(anyhow!("failed to get filename for {}",url))?;
+    sanitise_filename(&name)
+}
+
+/// errors from `download_file` that may be worth retrying, see `is_transient`.
+#[derive(thiserror::Error, Debug)]
+pub enum DownloadError {
+    #[error("download URL HTTP error: {}",.0.as_str())]
+    Http(StatusCode),
+    #[error("download timed out")]
+    Timeout,
+    /// the downloaded file didnt match the hash msgraph reported for it. the file is deleted.
+    #[error("downloaded file is corrupt: quickXorHash {actual} does not match expected {expected}")]
+    HashMismatch { expected: String, actual: String },
+    /// the host asked us to wait longer than the retry policy has left to wait.
+    #[error("{host} is throttling requests for another {}s, longer than is left to wait",.wait.as_secs())]
+    Throttled { host: String, wait: Duration },
+}
+
+/// whether a failed download is worth retrying: dropped connections, timeouts and server side errors are,
+/// client errors (4xx) and local file errors are not.
+pub fn is_transient(err: &Error) -> bool {
+    fn reqwest_transient(e: &reqwest::Error) -> bool {
+        e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode()
+    }
+    if let Some(e) = err.downcast_ref::<DownloadError>() {
+        return match e {
+            DownloadError::Http(status) => status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT || *status == StatusCode::TOO_MANY_REQUESTS,
+            DownloadError::Timeout => true,
+            DownloadError::HashMismatch { .. } | DownloadError::Throttled { .. } => false,
+        };
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return reqwest_transient(e);
+    }
+    //errors from the response body stream are wrapped in io errors
+    if let Some(e) = err.downcast_ref::<std::io::Error>() {
+        return e.get_ref().and_then(|e| e.downcast_ref::<reqwest::Error>()).is_some_and(reqwest_transient);
+    }
+    false
+}
+
+/// how `download_file` retries transient failures. delays double each attempt up to `max_backoff_ms`, with random jitter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// give up once the total time spent waiting between retries or for throttling would exceed this.
+    pub max_total_wait_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 8,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60 * 1000,
+            max_total_wait_secs: 10 * 60,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn max_total_wait(&self) -> Duration {
+        Duration::from_secs(self.max_total_wait_secs)
+    }
+
+    /// delay before retry number `attempt` (starting from 0), between half and all of the capped exponential backoff.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self.initial_backoff_ms.saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX)).min(self.max_backoff_ms);
+        //RandomState is randomly seeded, good enough for jitter without pulling in a rng
+        let jitter = RandomState::new().build_hasher().finish() % (cap / 2 + 1);
+        Duration::from_millis(cap - jitter)
+    }
+}
+
+/// a fixed set of progress bars shared between concurrent transfers. the number of bars bounds how many transfers run at once,
+/// each running transfer holds a `ProgressSlot` and reports through its bar.
+#[derive(Clone)]
+pub struct ProgressPool {
+    permits: Arc<Semaphore>,
+    bars: Arc<Mutex<Vec<ProgressBar>>>,
+}
+
+impl ProgressPool {
+    pub fn new(bars: Vec<ProgressBar>) -> Self {
+        ProgressPool {
+            permits: Arc::new(Semaphore::new(bars.len())),
+            bars: Arc::new(Mutex::new(bars)),
+        }
+    }
+
+    /// waits for a free slot.
+    /// # Returns
+    /// None if cancelled whilst waiting.
+    pub async fn acquire(&self, cancel: &CancellationToken) -> Result<Option<ProgressSlot>,Error> {
+        let permit = tokio::select! {
+            _ = cancel.cancelled() => {
+                return Ok(None);
+            }
+            p = self.permits.clone().acquire_owned() => p?
+        };
+        let bar = self.bars.lock().unwrap().pop().ok_or(anyhow!("progress pool has no free bars"))?;
+        bar.reset();
+        Ok(Some(ProgressSlot { bar: Some(bar), pool: self.bars.clone(), _permit: permit }))
+    }
+}
+
+/// a bar borrowed from a `ProgressPool`. the bar is cleared and returned to the pool on drop.
+pub struct ProgressSlot {
+    bar: Option<ProgressBar>,
+    pool: Arc<Mutex<Vec<ProgressBar>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for ProgressSlot {
+    type Target = ProgressBar;
+    fn deref(&self) -> &ProgressBar {
+        self.bar.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for ProgressSlot {
+    fn deref_mut(&mut self) -> &mut ProgressBar {
+        self.bar.as_mut().unwrap()
+    }
+}
+
+impl Drop for ProgressSlot {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+            self.pool.lock().unwrap().push(bar);
+        }
+    }
+}
+
 //wraps di so can cancel remaining items if an error occurs.
-pub async fn download_items(items: Vec<String>,mut progress: ProgressBar, title_buf: Arc<Mutex<String>>, finish: CancellationToken) -> Result<bool,Error> {
-    let ret = di(items,&mut progress,title_buf,&finish).await;
+//`force` skips delta updates so the full archives are downloaded and installed again.
+pub async fn download_items(items: Vec<String>, force: bool, progress: Vec<ProgressBar>, extract_progress: ProgressBar, title_buf: Arc<Mutex<String>>, finish: CancellationToken) -> Result<bool,Error> {
+    let ret = di(items,force,ProgressPool::new(progress),extract_progress,title_buf,&finish).await;
+    finish.cancel();
+    ret
+}
+
+//wraps ri so can cancel remaining items if an error occurs.
+//repairs each mod from the files its diff needs, as found by [dirhash::verify_installed_mods], without the full archive.
+pub async fn repair_items(diffs: BTreeMap<String,ManifestDiff>, progress: Vec<ProgressBar>, extract_progress: ProgressBar, title_buf: Arc<Mutex<String>>, finish: CancellationToken) -> Result<bool,Error> {
+    let ret = ri(diffs,ProgressPool::new(progress),extract_progress,title_buf,&finish).await;
     finish.cancel();
     ret
 }
@@ -40,37 +343,99 @@ pub fn fetcH_file_info(client: Client,
 
 /// generic file download for msgraph or normal links.
 /// alternative_tmp_id: id to use other than the url e.g. sharepoint drive + item ID for temp partial downloads
+/// transient failures (dropped connections, read timeouts, 5xx responses) are retried according to `retry`,
+/// resuming from the bytes already written each time. waits for throttling count towards its `max_total_wait_secs`. other failures such as 4xx responses are returned straight away.
+/// if `quick_xor_hash` is given the file is hashed as it downloads and checked against it. a corrupt file is deleted and downloaded again once.
+/// the file is saved as `file_name` if given, otherwise under the name the server gives it.
+/// if `manifest` is given, an existing temp file is only resumed if it was recorded there with the same `tmp_id` and eTag, otherwise it is discarded.
+/// without a manifest any existing file with the same name is resumed.
 /// # Returns
 /// path to the temporary file (partial or full) downloaded, or None if cancelled, or an Error.
-/// TODO !! sometimes get error decoding response body (pretty sure this is just internet disconnect)
 /// TODO split out to get download info, can use info from msgraph instead
 /// TODO: auth via github REST API + auth token for download link
 /// https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api
-pub async fn download_file(client: Client,
+pub async fn download_file(ctx: ClientCtx,
     display_name: String,
     dest_url: Url, 
     headers: Option<HeaderMap>, 
     dest_folder: &Path, 
+    file_name: Option<&str>,
     progress: &mut ProgressBar, 
     //unique ID for the file / url to download. This is base64 encoded along with the eTag to produce the temp file ID hash.
     //using a hasher to produce a consistent file name length <255.
     tmp_id: &str,
+    quick_xor_hash: Option<&str>,
+    manifest: Option<&Mutex<CACDownloadManifest>>,
+    retry: RetryPolicy,
+    cancel: CancellationToken
+    ) -> Result<Option<PathBuf>, Error> {
+    let mut attempt: u32 = 0;
+    let mut budget = retry.max_total_wait();
+    let mut redownloaded = false;
+    loop {
+        let err = match download_file_once(ctx.clone(),display_name.clone(),dest_url.clone(),headers.clone(),dest_folder,file_name,progress,tmp_id,quick_xor_hash,manifest,&mut budget,cancel.clone()).await {
+            Ok(r) => return Ok(r),
+            Err(e) => e
+        };
+        if let Some(DownloadError::HashMismatch { .. }) = err.downcast_ref::<DownloadError>() {
+            if redownloaded {
+                return Err(err);
+            }
+            warn!("{}, downloading {} again",err,dest_url);
+            redownloaded = true;
+            continue;
+        }
+        if !is_transient(&err) || attempt >= retry.max_retries {
+            return Err(err);
+        }
+        let delay = retry.backoff(attempt);
+        if delay > budget {
+            warn!("giving up on {} after waiting {}s in total",dest_url,(retry.max_total_wait() - budget).as_secs());
+            return Err(err);
+        }
+        attempt += 1;
+        budget -= delay;
+        warn!("download of {} failed ({}), retry {}/{} in {}ms",dest_url,err,attempt,retry.max_retries,delay.as_millis());
+        progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+        progress.set_message(format!(" {} failed: {}, retrying in {}s ({}/{})... ",display_name,err,delay.as_secs(),attempt,retry.max_retries));
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                return Ok(None);
+            }
+            _ = sleep(delay) => {}
+        }
+    }
+}
+
+async fn download_file_once(ctx: ClientCtx,
+    display_name: String,
+    dest_url: Url, 
+    headers: Option<HeaderMap>, 
+    dest_folder: &Path, 
+    file_name: Option<&str>,
+    progress: &mut ProgressBar, 
+    tmp_id: &str,
+    quick_xor_hash: Option<&str>,
+    manifest: Option<&Mutex<CACDownloadManifest>>,
+    //time left to wait for throttling, shared with the retries in `download_file`
+    budget: &mut Duration,
     cancel: CancellationToken
     ) -> Result<Option<PathBuf>, Error> {
-    progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_DOWNLOAD)?);
     std::fs::create_dir_all(dest_folder)?;
 
     let headers = headers.unwrap_or(HeaderMap::new());
     
     let mut head_headers = headers.clone();
     //msgraph links will reject a HEAD request, so do GET + drop
-    let response = client.get(dest_url.clone()).headers(head_headers.clone()).timeout(TIMEOUT).send().await?;
+    let response = tokio::select! {
+        _ = cancel.cancelled() => {
+            return Ok(None);
+        }
+        r = ctx.send_within(ctx.client.get(dest_url.clone()).headers(head_headers.clone()).timeout(TIMEOUT), Some(progress), budget) => r?
+    };
 
     if !response.status().is_success() {
-        return Err(anyhow!(
-            "download URL HTTP error: {}",
-            response.status().as_str()
-        ));
+        return Err(DownloadError::Http(response.status()).into());
     }
     let item_size: u64 = response.headers().get(header::CONTENT_LENGTH)
     .ok_or(anyhow!("Content-Length file size not in response header for {}",dest_url))?.to_str()?.parse()?;
@@ -82,25 +447,33 @@ pub async fn download_file(client: Client,
     // hasher.write(dest_url.as_str().as_bytes());
     // hasher.write(etag.as_bytes());
     // let fname: String =  BASE64_URL_SAFE_NO_PAD.encode(hasher.finalize());
-    let fname = match response.headers().get(header::CONTENT_DISPOSITION) {
-        None => return Err(anyhow!("failed to get filename")),
-        //TODO add support for filename*='<encoding>'<filename>
-        Some(v) => v.to_str()?.split("filename=").last().ok_or(anyhow!("failed to get filename"))?.replace("\"", "")
+    let fname = match file_name {
+        Some(f) => f.to_string(),
+        None => response_filename(response.headers(), response.url())?
     };
 
     //force close the connection
     drop(response);
 
 
-    //TODO
-    //also pass option<config> in for tests or edit return
-    //let mut tmp_manifest = CACDownloadManifest::read()?;
-    //tmp_manifest.0.insert(fname.clone(), TmpDownloadID{id: tmp_id.to_string(), etag: etag.clone()});
-
-    
-    let dest_path = dest_folder.join(fname);
+    let dest_path = dest_folder.join(&fname);
     warn!("dest_path: {}",dest_path.display());
 
+    //a partial file from another item or an older version of this one would be corrupted by appending to it
+    if let Some(manifest) = manifest {
+        let mut lock = manifest.lock().unwrap();
+        let id = TmpDownloadID{id: tmp_id.to_string(), etag: etag.clone()};
+        //checked against what's on disk, the downloader may be sharing the temp folder
+        *lock = CACDownloadManifest::update_or_default(|m| {
+            if !m.can_resume(&fname, &id) && std::fs::exists(&dest_path)? {
+                warn!("discarding stale partial download '{}'",dest_path.display());
+                remove_path(&dest_path)?;
+            }
+            m.0.insert(fname.clone(), id);
+            Ok(m.clone())
+        })?;
+    }
+
 
     warn!("downloading {}, size={}",dest_url,item_size);
 
@@ -118,9 +491,17 @@ pub async fn download_file(client: Client,
         file = std::fs::File::create(&dest_path)?;
     }
 
+    let mut hasher = quick_xor_hash.map(|_| QuickXorHash::new());
+    if start != 0 {
+        if let Some(h) = hasher.as_mut() {
+            progress.set_message(format!("Verifying {}", display_name));
+            hash_file(&dest_path, h)?;
+        }
+    }
+
     if start >= item_size {
+        verify_quick_xor(&dest_path, hasher, quick_xor_hash)?;
         return Ok(Some(dest_path));
-        progress.set_message(format!("Downloading {}", display_name));
     }
 
     let mut get_headers = headers;
@@ -131,24 +512,30 @@ pub async fn download_file(client: Client,
     progress.set_length(item_size);
     progress.set_message(format!("Downloading {}", display_name));
 
-    let response = client.get(dest_url).headers(get_headers)
+    let request = ctx.client.get(dest_url).headers(get_headers)
     //we cant disable the timeout by passing None
     //https://github.com/seanmonstar/reqwest/issues/1366
     //unset timeout as dont know how long large files will take. instead timeout for recieving data blocks
-    .timeout(Duration::MAX) 
-    .send().await?;
+    .timeout(Duration::MAX);
+    let response = tokio::select! {
+        _ = cancel.cancelled() => {
+            return Ok(None);
+        }
+        r = ctx.send_within(request, Some(progress), budget) => r?
+    };
+    //the throttle wait may have changed the style
+    progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_DOWNLOAD)?);
 
     if (!response.status().is_success()) {
-        return Err(anyhow!(
-            "download URL HTTP error: {}",
-            response.status().as_str()
-        ));
+        return Err(DownloadError::Http(response.status()).into());
     }
 
     if start != 0 && response.status() != StatusCode::PARTIAL_CONTENT {
         warn!("didnt recieve '206 Partial Content' response when trying to do a partial download / range request.");
-        file.set_len(0);
-        file.seek(std::io::SeekFrom::Start(0));
+        file.set_len(0)?;
+        file.seek(std::io::SeekFrom::Start(0))?;
+        start = 0;
+        hasher = hasher.map(|_| QuickXorHash::new());
     }
 
     //BufReader wont read more than 16KB anyway most likely due to max MTU size
@@ -166,108 +553,396 @@ pub async fn download_file(client: Client,
             readBytes = reader.read(&mut buf[..BLOCK_SIZE]) => {
                 let readBytes = readBytes?;
                 if(readBytes==0) {break;}
-                file.write(&buf[..readBytes])?;
+                file.write_all(&buf[..readBytes])?;
+                if let Some(h) = hasher.as_mut() {
+                    h.update(&buf[..readBytes]);
+                }
                 progress.inc(readBytes as u64);
             }
             _ = sleep(TIMEOUT) => {
-                return Err(anyhow!("download timed out"));
+                return Err(DownloadError::Timeout.into());
             }
         };
     }
+    drop(file);
+    verify_quick_xor(&dest_path, hasher, quick_xor_hash)?;
     progress.reset(); //TODO should be calling finish_and_clear() and then creating a new progress bar - make a custom progress indicator
     return Ok(Some(dest_path));
 }
 
-async fn di(items: Vec<String>,progress: &mut ProgressBar, title_buf: Arc<Mutex<String>>, finish: &CancellationToken) -> Result<bool,Error>{
-            let mut config = CACConfig::read()?;
+/// feeds the existing contents of a file into `hasher`, for resumed downloads.
+fn hash_file(path: &Path, hasher: &mut QuickXorHash) -> Result<(),Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// checks the hash of a finished download, deleting the file if it doesnt match so it isnt resumed.
+fn verify_quick_xor(path: &Path, hasher: Option<QuickXorHash>, expected: Option<&str>) -> Result<(),Error> {
+    if let (Some(hasher), Some(expected)) = (hasher, expected) {
+        let actual = hasher.finalize_base64();
+        if actual != expected {
+            std::fs::remove_file(path)?;
+            return Err(DownloadError::HashMismatch { expected: expected.to_string(), actual }.into());
+        }
+    }
+    Ok(())
+}
+
+/// # Returns
+/// the size of the file a link points to, or None if it isn't a sharepoint link.
+async fn link_size(ctx: ClientCtx, token: String, link: String) -> Result<Option<u64>,Error> {
+    let final_url = final_url(&ctx, Url::parse(&link)?).await?;
+    match msgraph::is_sharepoint_link(&final_url.authority())? {
+        true => Ok(Some(msgraph::get_shared_drive_item(ctx, token, Url::parse(&link)?, None).await?.size)),
+        false => Ok(None)
+    }
+}
+
+/// looks up the download size of each item from graph, summing the parts of multi link items, `limit` items at a time.
+/// items with a link that isn't on sharepoint are left out, as their size isn't known without downloading.
+/// items whose lookup fails are logged and left out too.
+pub async fn content_sizes(items: Vec<(String,Links)>, limit: usize) -> Result<HashMap<String,u64>,Error> {
+    let ctx = ClientCtx::build()?;
+    let token = msgraph::login(&ctx.client).await?;
+    Ok(stream::iter(items).map(|(name,links)| {
+        let (ctx, token) = (ctx.clone(), token.clone());
+        async move {
+            let mut size = Some(0);
+            for link in &links {
+                size = match link_size(ctx.clone(), token.clone(), link.clone()).await {
+                    Ok(s) => size.zip(s).map(|(a,b)| a + b),
+                    Err(e) => {
+                        warn!("failed to get the size of {} from {}: {}",name,link,e);
+                        None
+                    }
+                };
+                if size.is_none() {
+                    break;
+                }
+            }
+            size.map(|s| (name, s))
+        }
+    }).buffer_unordered(limit.max(1)).filter_map(|x| async move { x }).collect().await)
+}
+
+/// downloads one link into the temp folder, waiting for a free slot in the pool first.
+/// # Returns
+/// path to the downloaded file, or None if cancelled.
+async fn download_link(ctx: ClientCtx, token: String, display_name: String, link: String, retry: RetryPolicy, manifest: Arc<Mutex<CACDownloadManifest>>, pool: ProgressPool, finish: CancellationToken) -> Result<Option<PathBuf>,Error> {
+    let link_url = match Url::parse(&link) {
+        Ok(u) => u,
+        //not a valid url. assume is a reference to an optional mod in another mod
+        //or whatever you wanna add later
+        Err(e) => {
+            //TODO not impl
+            return Err(e.into());
+        }
+    };
+
+    let mut progress = match pool.acquire(&finish).await? {
+        Some(p) => p,
+        None => return Ok(None)
+    };
+    progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+    progress.set_message(format!(" Fetching info for {}... ",display_name));
+
+    let final_url = final_url(&ctx, link_url.clone()).await?;
+    warn!("link: {}",link);
+    match msgraph::is_sharepoint_link(&final_url.authority())? {
+        true => {
+            let item = msgraph::get_shared_drive_item(ctx.clone(), token.clone(),link_url,Some(&progress)).await?;
+            msgraph::download_item(ctx, token,item, TMP_FOLDER.display().to_string(), &mut progress, Some(&manifest), retry, finish).await
+        }
+        false => {
+            //generic link download
+            download_file(ctx,display_name,final_url.clone(),None,TMP_FOLDER.as_path(),None,&mut progress,
+                                //TODO tmp id ends up too long on windows using final dest url, errors out at fs::exists
+            final_url.as_str(),None,Some(&manifest),retry,finish).await
+        }
+    }
+}
+
+/// downloads one file of a mod on its own from its [ModManifest::blob_url], waiting for a free slot in the pool first.
+/// blobs are named by their hash, so it's saved per item as `.{item}.{hash}.blob` in case another item needs the same one.
+/// # Returns
+/// path to the downloaded file, or None if cancelled.
+async fn download_blob(ctx: ClientCtx, item: &str, display_name: String, url: String, hash: &str, retry: RetryPolicy, manifest: Arc<Mutex<CACDownloadManifest>>, pool: ProgressPool, finish: CancellationToken) -> Result<Option<PathBuf>,Error> {
+    let url = Url::parse(&url)?;
+    let mut progress = match pool.acquire(&finish).await? {
+        Some(p) => p,
+        None => return Ok(None)
+    };
+    let file_name = format!(".{}.{}.blob",item,hash);
+    download_file(ctx,display_name,url,None,TMP_FOLDER.as_path(),Some(&file_name),&mut progress,&format!("{}/{}",item,hash),None,Some(&manifest),retry,finish).await
+}
+
+/// updates an installed mod in place, downloading only the files that differ from `expected`, its published manifest.
+/// the installed files are hashed first, reusing digests from `hash_cache`.
+/// # Returns
+/// Some(false) if the mod isn't installed, `expected` has no blob url, or links differ, so the full archive is needed.
+/// None if cancelled.
+async fn delta_update(item: &str, mod_dir: &Path, expected: &ModManifest, hash_cache: Arc<Mutex<HashCache>>, ctx: ClientCtx, retry: RetryPolicy, manifest: Arc<Mutex<CACDownloadManifest>>, pool: ProgressPool, extract_progress: Arc<tokio::sync::Mutex<ProgressBar>>, finish: CancellationToken) -> Result<Option<bool>,Error> {
+    let target = mod_dir.join(item);
+    if expected.blob_url.is_none() || !target.is_dir() {
+        return Ok(Some(false));
+    }
+
+    //hashing is as heavy on the disk as extracting, so share its turn
+    let installed = {
+        let progress = extract_progress.lock().await;
+        let bar = progress.clone();
+        let (target, policy, cache, cancel) = (target.clone(), expected.link_policy, hash_cache.clone(), finish.clone());
+        let installed = tokio::task::spawn_blocking(move || -> Result<ModManifest,Error> {
+            let key = std::path::absolute(&target)?.to_string_lossy().to_string();
+            let mut folder = cache.lock().unwrap().0.remove(&key).unwrap_or_default();
+            let ret = ModManifest::build(&target, policy, &mut folder, false, &HashProgress::new(Some(bar)), &cancel);
+            HashCache::update_or_default(|c| {
+                c.0.insert(key.clone(), folder.clone());
+                Ok(())
+            })?;
+            cache.lock().unwrap().0.insert(key, folder);
+            ret
+        }).await?;
+        progress.finish_and_clear();
+        match installed {
+            Err(e) if matches!(e.downcast_ref(), Some(DirHashError::Cancelled)) => return Ok(None),
+            r => r?
+        }
+    };
+
+    let diff = expected.diff(&installed);
+    apply_diff(item, &target, expected, diff, ctx, retry, manifest, pool, extract_progress, finish).await
+}
+
+/// fixes the installed mod at `target` by downloading only the files `diff` needs from `expected`'s blob url,
+/// then applying them with [ModManifest::repair].
+/// # Returns
+/// Some(false) if a needed file has no blob, so the full archive is needed. None if cancelled.
+async fn apply_diff(item: &str, target: &Path, expected: &ModManifest, diff: ManifestDiff, ctx: ClientCtx, retry: RetryPolicy, manifest: Arc<Mutex<CACDownloadManifest>>, pool: ProgressPool, extract_progress: Arc<tokio::sync::Mutex<ProgressBar>>, finish: CancellationToken) -> Result<Option<bool>,Error> {
+    if diff.is_empty() {
+        return Ok(Some(true));
+    }
+    //files with the same contents share a blob, only fetch it once: hash -> (url, paths needing it)
+    let mut blobs: BTreeMap<&str, (String, Vec<&String>)> = BTreeMap::new();
+    for rel in diff.needed() {
+        match expected.blob(rel) {
+            Some(url) if expected.files[rel].link.is_none() => blobs.entry(&expected.files[rel].hash).or_insert((url, Vec::new())).1.push(rel),
+            _ => return Ok(Some(false))
+        }
+    }
+    warn!("delta updating {}: {} blobs to fetch, {} files to remove",item,blobs.len(),diff.added.len());
+
+    let fetched = try_join_all(blobs.iter().map(|(hash, (url, rels))| {
+        download_blob(ctx.clone(), item, format!("{}/{}",item,rels[0]), url.clone(), hash, retry, manifest.clone(), pool.clone(), finish.clone())
+    })).await?;
+    let fetched: Vec<PathBuf> = match fetched.into_iter().collect::<Option<Vec<_>>>() {
+        Some(b) => b,
+        None => return Ok(None)
+    };
+
+    //lay the files out like the mod so repair can copy them over
+    let staging = TMP_FOLDER.join(format!(".{}.delta",item));
+    let _ = remove_path(&staging);
+    {
+        let mut lock = manifest.lock().unwrap();
+        for ((_, rels), blob) in blobs.values().zip(&fetched) {
+            //the same blob may be needed at more than one path
+            for rel in rels {
+                let dest = dirhash::manifest_path(&staging, rel);
+                std::fs::create_dir_all(dest.parent().unwrap())?;
+                std::fs::copy(blob, &dest)?;
+            }
+        }
+        *lock = CACDownloadManifest::update_or_default(|m| {
+            for blob in &fetched {
+                m.remove(blob)?;
+            }
+            Ok(m.clone())
+        })?;
+    }
+
+    let progress = extract_progress.lock().await;
+    progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+    progress.set_message(format!(" patching {}...",item)); progress.set_length(1); progress.set_position(0);
+    let (expected, src, target) = (expected.clone(), staging.clone(), target.to_path_buf());
+    let repaired = tokio::task::spawn_blocking(move || expected.repair(&target, &src, &diff)).await?;
+    let _ = remove_path(&staging);
+    progress.finish_and_clear();
+    repaired?;
+    Ok(Some(true))
+}
+
+/// downloads all parts of an item concurrently, then extracts it once every part has arrived.
+/// only one extraction runs at a time, other items keep downloading meanwhile.
+/// mods are installed to `dest/item` whatever folder the archive puts them in.
+/// `password` decrypts the archive, DLC is installed into `dest` itself instead, see [extract::install_dlc].
+/// if `delta` is the mod's published manifest, only the changed files are fetched when possible, see [delta_update].
+/// the full archive is used if that isn't possible or fails.
+/// # Returns
+/// the item name once installed, or None if cancelled.
+async fn download_and_extract(item: String, links: Links, dest: PathBuf, password: Option<String>, delta: Option<ModManifest>, hash_cache: Arc<Mutex<HashCache>>, ctx: ClientCtx, token: String, retry: RetryPolicy, manifest: Arc<Mutex<CACDownloadManifest>>, pool: ProgressPool, extract_progress: Arc<tokio::sync::Mutex<ProgressBar>>, finish: CancellationToken) -> Result<Option<String>,Error> {
+    if let Some(expected) = delta {
+        match delta_update(&item, &dest, &expected, hash_cache, ctx.clone(), retry, manifest.clone(), pool.clone(), extract_progress.clone(), finish.clone()).await {
+            Ok(Some(true)) => return Ok(Some(item)),
+            Ok(None) => return Ok(None),
+            Ok(Some(false)) => warn!("no delta update for {}, downloading the full archive",item),
+            Err(e) => warn!("delta update of {} failed, downloading the full archive: {:#}",item,e)
+        }
+    }
+
+    let parts = try_join_all(links.into_iter().map(|link| {
+        download_link(ctx.clone(), token.clone(), item.clone(), link.clone(), retry, manifest.clone(), pool.clone(), finish.clone())
+    })).await?;
+    let files: Vec<PathBuf> = match parts.into_iter().collect::<Option<Vec<_>>>() {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+
+    let progress = extract_progress.lock().await;
+    if finish.is_cancelled() {
+        return Ok(None);
+    }
+
+    //links aren't necessarily in volume order. the remaining parts are found next to the first
+    let files = extract::sort_volumes(files, |f| f.file_name().and_then(|n| n.to_str()).unwrap_or(""))?;
+    let archive = files.first().ok_or(anyhow!("no files downloaded for {}",item))?.clone();
+    let bar = progress.clone();
+    let cancel = finish.clone();
+    let name = item.clone();
+    let extracted = tokio::task::spawn_blocking(move || match password {
+        Some(pwd) => extract::install_dlc(&archive,&dest,&name,&pwd,Some(&bar),&cancel),
+        None => extract::install_mod(&archive,&dest,&name,Some(&bar),&cancel)
+    }).await?;
+    if let Err(e) = extracted {
+        return match e.downcast_ref::<ExtractError>() {
+            Some(ExtractError::Cancelled) => Ok(None),
+            Some(pe @ (ExtractError::WrongPassword | ExtractError::PasswordRequired)) => {
+                let msg = format!("could not decrypt '{}': {}, the content manifest may be out of date", item, pe);
+                Err(e.context(msg))
+            }
+            _ => Err(e)
+        };
+    }
+
+    progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+    progress.set_message(format!(" cleaning up {}...",item)); progress.set_length(1); progress.set_position(0);
+    {
+        let mut lock = manifest.lock().unwrap();
+        *lock = CACDownloadManifest::update_or_default(|m| {
+            for f in files {
+                m.remove(&f)?;
+            }
+            Ok(m.clone())
+        })?;
+    }
+    progress.finish_and_clear();
+    Ok(Some(item))
+}
+
+async fn ri(diffs: BTreeMap<String,ManifestDiff>, pool: ProgressPool, extract_progress: ProgressBar, title_buf: Arc<Mutex<String>>, finish: &CancellationToken) -> Result<bool,Error> {
+    let config = CACConfig::read()?;
+    let ctx = ClientCtx::build()?;
+    let published = ModsManifest::read()?;
+    let mod_dir = config.absolute_mod_dir()?;
+    let extract_progress = Arc::new(tokio::sync::Mutex::new(extract_progress));
+    let manifest = Arc::new(Mutex::new(match TMP_DOWNLOADS_FILE.is_file() {
+        true => CACDownloadManifest::read()?,
+        false => CACDownloadManifest::default()
+    }));
+
+    let total = diffs.len();
+    let mut tasks = JoinSet::new();
+    for (item, diff) in diffs {
+        let expected = published.0.get(&item).ok_or(anyhow!("no published manifest for '{}'",item))?.clone();
+        let target = mod_dir.join(&item);
+        let (ctx, retry, manifest, pool, extract_progress, finish) = (ctx.clone(), config.download_retry, manifest.clone(), pool.clone(), extract_progress.clone(), finish.clone());
+        tasks.spawn(async move {
+            match apply_diff(&item, &target, &expected, diff, ctx, retry, manifest, pool, extract_progress, finish).await? {
+                Some(true) => Ok(Some(item)),
+                Some(false) => Err(anyhow!("'{}' can't be repaired file by file, update it instead",item)),
+                None => Ok(None)
+            }
+        });
+    }
+
+    let mut done = 0;
+    while let Some(ret) = tasks.join_next().await {
+        let item = match ret?? {
+            Some(i) => i,
+            None => return Ok(false)
+        };
+        done += 1;
+        {
+            let mut lock = title_buf.lock().unwrap();
+            *lock = format!("Repair items: {}/{}",done,total);
+        }
+        CACConfig::update(|c| {
+            c.pending_updates.remove(&item);
+            c.failed_verification.remove(&item);
+            Ok(())
+        })?;
+    }
+    Ok(true)
+}
+
+async fn di(items: Vec<String>, force: bool, pool: ProgressPool, extract_progress: ProgressBar, title_buf: Arc<Mutex<String>>, finish: &CancellationToken) -> Result<bool,Error>{
+            let config = CACConfig::read()?;
             let client_ctx = ClientCtx::build()?; //TODO initialise elsewhere
             let token = msgraph::login(&client_ctx.client).await?;
 
-            //TODO indicate on n/total items
-
             //TODO: support for optional mods in other mods: sort items to copy out optional mods inside other mods after their parent
             //these wont be added to the update list in the config list atm if the parent needs updating - add logic to handle this,
             //and to update both if either are in the update list 
 
             let content = CACContent::read()?;
             let content_map =  content.content_map();
-            for (i,item) in items.iter().enumerate() {
-                {
-                    let mut lock = title_buf.lock().unwrap();
-                    *lock = format!("{}/{}",i,items.len());
-                }
-
-                let links  =content_map.get(&item.to_string()).unwrap();
-                
-                let mut files: Vec<PathBuf> = Vec::new();
-                for link in links.into_iter() {
-
-                    match Url::parse(link) {
-                        Ok(link_url) => {
-                                    
-                            let final_url = final_url(client_ctx.client.clone(), link_url.clone()).await?;
-                            
-                            progress.set_message(" Fetching info... ");
-                            warn!("link: {}",link);
-                            let optfile = match msgraph::is_sharepoint_link(&final_url.authority())? {
-                                true => {
-                                    let item = msgraph::get_shared_drive_item(client_ctx.client.clone(), token.clone(),link_url).await?;
-                                    msgraph::download_item(client_ctx.client.clone(), token.clone(),item, TMP_FOLDER.display().to_string(), progress, finish.clone()).await?
-                                    
-                                }
-                                false => {
-                                    //generic link download
-                                    download_file(client_ctx.client.clone(),item.clone(),final_url.clone(),None,TMP_FOLDER.as_path(),progress,
-                                                        //TODO tmp id ends up too long on windows using final dest url, errors out at fs::exists
-                                    final_url.as_str(),finish.clone()).await?
-                                }
-                            };
-                            let file = match optfile {
-                                Some(f) => f,
-                                None => return Ok(false)
-                            };
-                            files.push(file);                        
-                        }
-                        //not a valid url. assume is a reference to an optional mod in another mod
-                        //or whatever you wanna add later
-                        Err(e) => {
-                            //TODO not impl
-                            return Err(e.into());
-                        }
-
-                    }
-                }
-                let dest = match item.starts_with("@"){
-                    false => {PathBuf::from(&config.arma_path).parent().unwrap().display().to_string()}
-                    true => {config.absolute_mod_dir()?.display().to_string()}
+            let extract_progress = Arc::new(tokio::sync::Mutex::new(extract_progress));
+            let manifest = Arc::new(Mutex::new(CACDownloadManifest::read()?));
+            let published = match MODS_MANIFEST_FILE.is_file() {
+                true => ModsManifest::read()?,
+                false => ModsManifest::default()
+            };
+            let hash_cache = Arc::new(Mutex::new(match HASH_CACHE_FILE.is_file() {
+                true => HashCache::read()?,
+                false => HashCache::default()
+            }));
+
+            //dropping the set on an early return aborts the remaining items
+            let mut tasks = JoinSet::new();
+            for item in items.iter() {
+                let links = (*content_map.get(item).ok_or(anyhow!("'{}' is not in the content manifest",item))?).clone();
+                //dlc is encrypted and goes in the arma folder itself
+                let (dest, password, delta) = match content.dlc.get(item) {
+                    Some(dlc) => (config.arma_dir()?, Some(dlc.pwd.clone()), None),
+                    None => (config.absolute_mod_dir()?, None, published.0.get(item).filter(|_| !force).cloned())
                 };
+                tasks.spawn(download_and_extract(item.clone(), links, dest, password, delta, hash_cache.clone(), client_ctx.clone(), token.clone(), config.download_retry, manifest.clone(), pool.clone(), extract_progress.clone(), finish.clone()));
+            }
 
-                //TODO delete the old folder before unzipping if present
-                //need logic to make sure files are top level as if unzipping to .
-                //will unzip into e.g../@ace
-                //TODO double check getting archive .000
-
-                //TODO temp fix
-                let mut dest_folder = Path::new(&dest).join(item);
-                warn!("removing {} before unzip", dest_folder.display());
-                if dest_folder.exists() {
-                    if dest_folder.is_dir() {
-                        std::fs::remove_dir_all(&dest_folder)?;
-                    }else {
-                        return Err(anyhow!("refusing to remove '{}' as not a folder",dest_folder.display()));
-                    }
-                }
-
-                unzip(files.get(0).unwrap().to_str().unwrap(),&dest,Some(progress))?;
-
-                progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
-                progress.set_message(" cleaning up..."); progress.set_length(1); progress.set_position(0);
-                for f in files {
-                    std::fs::remove_file(f)?;
+            let mut done = 0;
+            while let Some(ret) = tasks.join_next().await {
+                let item = match ret?? {
+                    Some(i) => i,
+                    None => return Ok(false)
+                };
+                done += 1;
+                {
+                    let mut lock = title_buf.lock().unwrap();
+                    *lock = format!("Update items: {}/{}",done,items.len());
                 }
 
-                config.pending_updates.remove(item);
-                config.save()?;
+                CACConfig::update(|c| {
+                    c.pending_updates.remove(&item);
+                    c.failed_verification.remove(&item);
+                    Ok(())
+                })?;
             }
             Ok(true)
-        }
\ No newline at end of file
+        }
@@ -1,11 +1,12 @@
-use std::{fs::{self, File}, io::{Read, Write}, path::PathBuf};
+use std::{fs, io::Read, path::{Path, PathBuf}, sync::Arc};
 use anyhow::{anyhow, Error};
 use clap::Parser;
 use colored::Colorize;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use futures_util::future::try_join_all;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::Url;
-use src_backend::{msgraph::SharedDriveItem, *};
+use src_backend::{download::{ProgressPool, RetryPolicy}, msgraph::SharedDriveItem, *};
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 
@@ -15,6 +16,15 @@ struct Args {
 
     #[arg(short,default_value = "")]
     output_dir: String,
+
+    #[arg(short,long,default_value_t = configs::DEFAULT_MAX_CONCURRENT_DOWNLOADS, help="number of files to download at once")]
+    jobs: usize,
+
+    #[arg(long,default_value_t = RetryPolicy::default().max_retries, help="number of times to retry a failed download")]
+    retries: u32,
+
+    #[arg(long,default_value_t = RetryPolicy::default().max_total_wait_secs, help="maximum total seconds to wait between retries of a download")]
+    max_retry_wait: u64,
     
     #[clap(flatten)]
     args: ArgsGroup,
@@ -64,22 +74,16 @@ async fn main() -> Result<(),Error> {
         urls.append(&mut urls_in);
     }
 
-    if urls.len()==0 {
+    if urls.is_empty() {
         println!("{}","no URL's provided to download.".yellow());
         return Ok(());
     }
 
-    let _z7 = FileAutoDeleter::new("7za.exe"); //allows file to be deleted automatically even if theres an error
-    { //scope so file is closed before running process
-        let mut z7 = File::create("7za.exe")?;
-        z7.write_all(Z7_EXE).map_err(|_| anyhow!("failed to unpack 7za.exe"))?;
-    }
-
     //grab info first and group partial archives
     println!("Fetching link info...");
     let mut tasks = JoinSet::new(); 
     urls.iter().map(|u| Url::parse(u).map_err(|e| anyhow!(e))).collect::<Result<Vec<Url>,Error>>()?
-    .iter().for_each(|u| {tasks.spawn(msgraph::get_shared_drive_item(ctx.client.clone(), token.clone(),u.clone()));});
+    .iter().for_each(|u| {tasks.spawn(msgraph::get_shared_drive_item(ctx.clone(), token.clone(),u.clone(),None));});
     let drive_items: Vec<SharedDriveItem>   = tasks.join_all().await.into_iter().collect::<Result<_,_>>()?;
     let items = group_drive_item_archives(drive_items)?;
 
@@ -93,39 +97,73 @@ async fn main() -> Result<(),Error> {
 
     println!("Downloading {} files...",urls.len());
 
-    //TODO 
-    // limit number of running downloads. unzip can be parallel, but all previous downloads for a split archive need to be downloaded first
-    for item in &items {
-        let mut parts: Vec<PathBuf> = Vec::new();
-        for part in &item.1 {
-            let mut progress = ProgressBar::new(0).with_style(ProgressStyle::with_template(PROGRESS_STYLE_DOWNLOAD)?);//TODO static assert usize::MAX<= u64::MAX
-            let p =msgraph::download_item(ctx.client.clone(),token.clone(), part.clone(), args.output_dir.clone(),&mut progress, shutdown.clone()).await?;
-            let part = match p {
-                Some(p) => p,
-                None => {
-                    println!("{}",format!("Download cancelled.").bold().bright_yellow());
-                    return Ok(());
+    // limit number of running downloads. unzip can run alongside, but all previous downloads for a split archive need to be downloaded first
+    let bars = MultiProgress::new();
+    let pool = ProgressPool::new((0..args.jobs.max(1)).map(|_| {
+        bars.add(ProgressBar::new(0).with_style(ProgressStyle::with_template(PROGRESS_STYLE_DOWNLOAD).unwrap()))//TODO static assert usize::MAX<= u64::MAX
+    }).collect());
+    let extract_lock = Arc::new(tokio::sync::Mutex::new(()));
+    let retry = RetryPolicy { max_retries: args.retries, max_total_wait_secs: args.max_retry_wait, ..Default::default() };
+
+    let mut tasks = JoinSet::new();
+    for item in items {
+        let (ctx, token, output_dir, pool, bars, extract_lock, shutdown) =
+            (ctx.clone(), token.clone(), args.output_dir.clone(), pool.clone(), bars.clone(), extract_lock.clone(), shutdown.clone());
+        tasks.spawn(async move {
+            let parts = try_join_all(item.1.iter().map(|part| {
+                let (ctx, token, output_dir, pool, shutdown) = (ctx.clone(), token.clone(), output_dir.clone(), pool.clone(), shutdown.clone());
+                async move {
+                    let mut progress = match pool.acquire(&shutdown).await? {
+                        Some(p) => p,
+                        None => return Ok(None)
+                    };
+                    msgraph::download_item(ctx,token, part.clone(), output_dir,&mut progress, None, retry, shutdown).await
                 }
+            })).await?;
+            let parts: Vec<PathBuf> = match parts.into_iter().collect::<Option<Vec<_>>>() {
+                Some(p) => p,
+                None => return Ok::<bool,Error>(false)
             };
-            parts.push(part);
-        }
-
-        //7zip will automatically find and extract the remaining parts
-        let mut z7_progress = ProgressBar::new_spinner().with_style(
-            ProgressStyle::with_template(PROGRESS_STYLE_EXTRACT)?);
-        z7_progress.set_length(100);
 
-        //TODO delete the old folder before unzipping if present
-        //TODO double check getting archive .000
-        
-        unzip(parts.get(0).unwrap().as_os_str().to_str().unwrap(),".",Some(&mut z7_progress))?;
-        println!("{}",format!("Extracted {}",&item.1[0].name).bold().green());
+            let _extracting = extract_lock.lock().await;
+
+            //the remaining parts are found next to the first
+            let z7_progress = bars.add(ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template(PROGRESS_STYLE_EXTRACT)?));
+            z7_progress.set_length(100);
+
+            //TODO delete the old folder before unzipping if present
+            
+            let archive = parts.first().unwrap().clone();
+            let name = item.1[0].name.clone();
+            let cancel = shutdown.clone();
+            let extracted = tokio::task::spawn_blocking(move || {
+                let ret = extract::extract_archive(&archive,Path::new("."),None,Some(&z7_progress),&cancel);
+                z7_progress.finish_and_clear();
+                ret
+            }).await?;
+            if let Err(e) = extracted {
+                return match e.downcast_ref::<extract::ExtractError>() {
+                    Some(extract::ExtractError::Cancelled) => Ok(false),
+                    _ => Err(e)
+                };
+            }
+            bars.println(format!("Extracted {}",name).bold().green().to_string())?;
+
+            //remove archive or all partial archives
+            for p in parts {
+                fs::remove_file(p)?;
+            }
+            Ok(true)
+        });
+    }
 
-        //remove archive or all partial archives
-        for p in parts {
-            fs::remove_file(p)?;
+    while let Some(ret) = tasks.join_next().await {
+        if !ret?? {
+            println!("{}","Download cancelled.".bold().bright_yellow());
+            return Ok(());
         }
     }
     
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,587 @@
+use std::{ffi::{OsStr, OsString}, fs::{self, File}, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+use anyhow::{anyhow, Error};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{PROGRESS_STYLE_EXTRACT, download::remove_path, unzip};
+
+const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
+const ZIP_MAGIC: &[u8] = b"PK";
+const CHUNK_SIZE: usize = 64 * 1024;
+/// how many folders deep into an archive to look for the mod folder.
+const MOD_ROOT_MAX_DEPTH: usize = 4;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExtractError {
+    #[error("'{0}' is not a 7z or zip archive")]
+    UnsupportedFormat(PathBuf),
+    #[error("unsupported archive feature: {0}")]
+    Unsupported(String),
+    #[error("archive is password protected")]
+    PasswordRequired,
+    #[error("wrong password for archive")]
+    WrongPassword,
+    #[error("archive is corrupt: {0}")]
+    Corrupt(String),
+    /// an entry's crc didn't match, which is what a wrong key looks like in an encrypted archive.
+    #[error("checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+    #[error("entry '{0}' would be extracted outside of the destination folder")]
+    UnsafePath(String),
+    #[error("archive for '{name}' can't be mapped to a mod folder: {reason}")]
+    ModLayout { name: String, reason: String },
+    #[error("parts of '{archive}' don't make a complete archive: {reason}")]
+    BadVolumes { archive: String, reason: String },
+    #[error("extraction cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ExtractError {
+    /// whether the bundled 7za might manage an archive the native backend couldn't.
+    pub fn can_fall_back(&self) -> bool {
+        matches!(self, Self::UnsupportedFormat(_) | Self::Unsupported(_))
+    }
+}
+
+impl From<sevenz_rust2::Error> for ExtractError {
+    fn from(e: sevenz_rust2::Error) -> Self {
+        use sevenz_rust2::Error as E;
+        match e {
+            E::PasswordRequired | E::MaybeBadPassword(_) => Self::PasswordRequired,
+            E::Io(e, _) | E::FileOpen(e, _) => Self::Io(e),
+            E::UnsupportedCompressionMethod(m) => Self::Unsupported(m),
+            E::UnsupportedVersion { major, minor } => Self::Unsupported(format!("7z version {}.{}", major, minor)),
+            E::ExternalUnsupported | E::Unsupported(_) | E::MaxMemLimited { .. } => Self::Unsupported(e.to_string()),
+            E::ChecksumVerificationFailed => Self::ChecksumMismatch(e.to_string()),
+            e => Self::Corrupt(e.to_string()),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for ExtractError {
+    fn from(e: zip::result::ZipError) -> Self {
+        use zip::result::ZipError as E;
+        match e {
+            E::UnsupportedArchive(E::PASSWORD_REQUIRED) | E::InvalidPassword => Self::PasswordRequired,
+            E::Io(e) => Self::Io(e),
+            E::UnsupportedArchive(_) | E::CompressionMethodNotSupported(_) => Self::Unsupported(e.to_string()),
+            e => Self::Corrupt(e.to_string()),
+        }
+    }
+}
+
+/// a file's place in a split archive, parsed from its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    /// name of the whole archive, e.g. `@ace.7z` for `@ace.7z.001` or `@ace.rar` for `@ace.part1.rar`.
+    pub archive: String,
+    pub number: u32,
+}
+
+/// parses `name.7z.001`, `name.zip.002` and `name.partN.rar` style names.
+/// a file that isn't split is volume 1 of itself.
+pub fn parse_volume(fname: &str) -> Volume {
+    static SPLIT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+)\.([0-9]{3,})$").unwrap());
+    static RAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^(.+)\.part([0-9]+)(\.rar)$").unwrap());
+
+    if let Some(c) = SPLIT.captures(fname) {
+        if let Ok(number) = c[2].parse() {
+            return Volume { archive: c[1].to_string(), number };
+        }
+    }
+    if let Some(c) = RAR.captures(fname) {
+        if let Ok(number) = c[2].parse() {
+            return Volume { archive: format!("{}{}", &c[1], &c[3]), number };
+        }
+    }
+    Volume { archive: fname.to_string(), number: 1 }
+}
+
+/// orders the parts of one split archive by volume number, so the first volume comes first.
+/// numbering may start at 0 or 1.
+/// # Returns
+/// [ExtractError::BadVolumes] if a volume is missing, repeated, or from another archive.
+pub fn sort_volumes<T>(parts: Vec<T>, name: impl Fn(&T) -> &str) -> Result<Vec<T>, ExtractError> {
+    let mut parts: Vec<(Volume, T)> = parts.into_iter().map(|p| (parse_volume(name(&p)), p)).collect();
+    parts.sort_by_key(|p| p.0.number);
+
+    let archive = match parts.first() {
+        Some(p) => p.0.archive.clone(),
+        None => return Ok(Vec::new())
+    };
+    let bad = |reason: String| ExtractError::BadVolumes { archive: archive.clone(), reason };
+
+    if let Some(other) = parts.iter().find(|p| p.0.archive != archive) {
+        return Err(bad(format!("'{}' is part of '{}'", name(&other.1), other.0.archive)));
+    }
+    let first = parts[0].0.number;
+    if first > 1 {
+        return Err(bad(format!("volume {} is missing", first - 1)));
+    }
+    for (i, w) in parts.windows(2).enumerate() {
+        if w[0].0.number == w[1].0.number {
+            return Err(bad(format!("volume {} appears more than once", w[1].0.number)));
+        }
+        if w[1].0.number != first + i as u32 + 1 {
+            return Err(bad(format!("volume {} is missing", first + i as u32 + 1)));
+        }
+    }
+    Ok(parts.into_iter().map(|p| p.1).collect())
+}
+
+/// groups files into the archives they belong to, each sorted with [sort_volumes].
+/// archives are kept in the order they first appear.
+pub fn group_volumes<T>(items: Vec<T>, name: impl Fn(&T) -> &str) -> Result<Vec<(String, Vec<T>)>, ExtractError> {
+    let mut groups: Vec<(String, Vec<T>)> = Vec::new();
+    for i in items {
+        let archive = parse_volume(name(&i)).archive;
+        match groups.iter().position(|g| g.0 == archive) {
+            Some(idx) => groups[idx].1.push(i),
+            None => groups.push((archive, vec![i]))
+        }
+    }
+    groups.into_iter().map(|(archive, parts)| Ok((archive, sort_volumes(parts, &name)?))).collect()
+}
+
+/// reads a byte-split multi-volume set (`name.7z.001`, `name.7z.002`, ...) as one continuous file.
+/// a single file that isn't numbered is just read as is.
+pub struct MultiVolumeReader {
+    /// each volume with the offset it starts at in the joined stream.
+    volumes: Vec<(File, u64)>,
+    len: u64,
+    pos: u64,
+}
+
+impl MultiVolumeReader {
+    /// opens `first` along with any following volumes next to it.
+    pub fn open(first: &Path) -> io::Result<Self> {
+        Self::from_paths(&volumes(first))
+    }
+
+    pub fn from_paths(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut volumes = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for p in paths {
+            let f = File::open(p)?;
+            let size = f.metadata()?.len();
+            volumes.push((f, len));
+            len += size;
+        }
+        Ok(Self { volumes, len, pos: 0 })
+    }
+
+    /// total size of all volumes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl Read for MultiVolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let idx = self.volumes.partition_point(|(_, start)| *start <= self.pos) - 1;
+        let end = self.volumes.get(idx + 1).map(|v| v.1).unwrap_or(self.len);
+        let (file, start) = &mut self.volumes[idx];
+        file.seek(SeekFrom::Start(self.pos - *start))?;
+        let n = buf.len().min((end - self.pos) as usize);
+        let read = file.read(&mut buf[..n])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for MultiVolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = new.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of archive"))?;
+        Ok(self.pos)
+    }
+}
+
+/// lists `first` and the volumes following it, stopping at the first gap.
+/// # Returns
+/// just `first` if it isn't numbered like `.001`.
+pub fn volumes(first: &Path) -> Vec<PathBuf> {
+    let mut ret = vec![first.to_path_buf()];
+    let ext = match first.extension().and_then(|e| e.to_str()) {
+        Some(e) if e.len() >= 3 && e.bytes().all(|b| b.is_ascii_digit()) => e,
+        _ => return ret
+    };
+    let width = ext.len();
+    let mut n: u64 = ext.parse().unwrap_or(0);
+    loop {
+        n += 1;
+        let next = first.with_extension(format!("{:0width$}", n));
+        if !next.is_file() {
+            break;
+        }
+        ret.push(next);
+    }
+    ret
+}
+
+/// joins an archive entry's name onto `dest`.
+/// rejects names that would escape it, such as `../x` or `C:\x`.
+fn entry_path(dest: &Path, name: &str) -> Result<PathBuf, ExtractError> {
+    let mut path = dest.to_path_buf();
+    let mut empty = true;
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(ExtractError::UnsafePath(name.to_string())),
+            p if p.contains(':') => return Err(ExtractError::UnsafePath(name.to_string())),
+            p => { path.push(p); empty = false; }
+        }
+    }
+    match empty {
+        true => Err(ExtractError::UnsafePath(name.to_string())),
+        false => Ok(path)
+    }
+}
+
+/// copies one entry to disk in chunks, checking for cancellation in between.
+fn write_entry(path: &Path, reader: &mut dyn Read, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), ExtractError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        if cancel.is_cancelled() {
+            return Err(ExtractError::Cancelled);
+        }
+        //decoding errors come through the entry's reader
+        let n = reader.read(&mut buf).map_err(read_error)?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])?;
+        if let Some(p) = progress {
+            p.inc(n as u64);
+        }
+    }
+    Ok(())
+}
+
+/// sorts crc failures from other decoding errors, both backends report them through the entry's reader.
+fn read_error(e: io::Error) -> ExtractError {
+    let crc = match e.get_ref() {
+        Some(inner) => matches!(inner.downcast_ref(), Some(sevenz_rust2::Error::ChecksumVerificationFailed)) || inner.to_string() == "Invalid checksum",
+        None => false
+    };
+    match crc {
+        true => ExtractError::ChecksumMismatch(e.to_string()),
+        false => ExtractError::Corrupt(e.to_string())
+    }
+}
+
+fn start_progress(progress: Option<&ProgressBar>, total: u64) {
+    if let Some(p) = progress {
+        p.set_style(ProgressStyle::with_template(PROGRESS_STYLE_EXTRACT).unwrap());
+        p.set_message("...");
+        p.set_length(total);
+        p.set_position(0);
+    }
+}
+
+fn extract_7z(source: MultiVolumeReader, dest: &Path, password: Option<&str>, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), ExtractError> {
+    let pwd = password.map(sevenz_rust2::Password::new).unwrap_or(sevenz_rust2::Password::empty());
+    let mut reader = sevenz_rust2::ArchiveReader::new(source, pwd)?;
+    let total = reader.archive().files.iter().map(|f| f.size).sum();
+    start_progress(progress, total);
+
+    //errors of our own are kept here, the closure can only return sevenz errors
+    let mut failed: Option<ExtractError> = None;
+    reader.for_each_entries(|entry, r| {
+        let ret = entry_path(dest, &entry.name).and_then(|path| {
+            if let Some(p) = progress {
+                p.set_message(entry.name.clone());
+            }
+            match entry.is_directory {
+                true => fs::create_dir_all(&path).map_err(ExtractError::from),
+                false => write_entry(&path, r, progress, cancel)
+            }
+        });
+        match ret {
+            Ok(()) => Ok(true),
+            Err(e) => { failed = Some(e); Ok(false) }
+        }
+    })?;
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
+
+fn extract_zip(source: MultiVolumeReader, dest: &Path, password: Option<&str>, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), ExtractError> {
+    let mut archive = zip::ZipArchive::new(source)?;
+    let total = archive.decompressed_size().unwrap_or(0).try_into().unwrap_or(u64::MAX);
+    start_progress(progress, total);
+
+    for i in 0..archive.len() {
+        let mut file = match password {
+            Some(pwd) => archive.by_index_decrypt(i, pwd.as_bytes())?,
+            None => archive.by_index(i)?
+        };
+        let name = file.name()?.to_string();
+        let path = entry_path(dest, &name)?;
+        if let Some(p) = progress {
+            p.set_message(name);
+        }
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+        } else {
+            write_entry(&path, &mut file, progress, cancel)?;
+        }
+    }
+    Ok(())
+}
+
+/// extracts a .7z or .zip archive into `dest` in-process, keeping the paths stored in the archive.
+/// for multi-volume sets pass the first volume, the rest are found next to it.
+/// progress is reported in bytes written out of the total uncompressed size.
+/// encrypted archives need `password`, a wrong one gives [ExtractError::WrongPassword].
+pub fn extract(archive: &Path, dest: &Path, password: Option<&str>, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), ExtractError> {
+    let mut source = MultiVolumeReader::open(archive)?;
+    let mut magic = [0u8; 6];
+    let n = source.read(&mut magic)?;
+    source.seek(SeekFrom::Start(0))?;
+
+    fs::create_dir_all(dest)?;
+    let ret = if magic[..n].starts_with(SEVENZ_MAGIC) {
+        extract_7z(source, dest, password, progress, cancel)
+    } else if magic[..n].starts_with(ZIP_MAGIC) {
+        extract_zip(source, dest, password, progress, cancel)
+    } else {
+        Err(ExtractError::UnsupportedFormat(archive.to_path_buf()))
+    };
+    //a wrong key decrypts to garbage, which shows up as a crc mismatch. anything else is a damaged archive
+    match (ret, password) {
+        (Err(ExtractError::PasswordRequired | ExtractError::ChecksumMismatch(_)), Some(_)) => Err(ExtractError::WrongPassword),
+        (ret, _) => ret
+    }
+}
+
+/// extracts with [extract], falling back to the bundled 7za on windows for anything it can't handle.
+/// # Returns
+/// the [ExtractError] as the error's root cause when the native backend fails for good.
+pub fn extract_archive(archive: &Path, dest: &Path, password: Option<&str>, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), Error> {
+    match extract(archive, dest, password, progress, cancel) {
+        Ok(()) => Ok(()),
+        Err(e) if e.can_fall_back() && cfg!(windows) => {
+            warn!("native extraction of {} failed ({}), falling back to 7za", archive.display(), e);
+            let mut bar = progress.cloned();
+            unzip(&archive.display().to_string(), &dest.display().to_string(), password, bar.as_mut())
+        }
+        Err(e) => Err(anyhow!(e).context(format!("failed to extract {}", archive.display())))
+    }
+}
+
+/// case insensitive, archives made on windows aren't consistent about `Addons` vs `addons`.
+fn has_child(dir: &Path, name: &str, is_dir: bool) -> bool {
+    fs::read_dir(dir).map(|entries| entries.flatten().any(|e| {
+        e.file_name().to_string_lossy().eq_ignore_ascii_case(name) && e.file_type().is_ok_and(|t| t.is_dir() == is_dir)
+    })).unwrap_or(false)
+}
+
+/// collects folders with an `addons/` folder, without looking inside them.
+fn mod_root_candidates(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    if has_child(dir, "addons", true) {
+        found.push(dir.to_path_buf());
+        return Ok(());
+    }
+    if depth == MOD_ROOT_MAX_DEPTH {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            mod_root_candidates(&entry.path(), depth + 1, found)?;
+        }
+    }
+    Ok(())
+}
+
+/// finds the mod folder in an extracted archive, the folder with `addons/` and `mod.cpp`.
+/// folders with only `addons/` are accepted if none have both. if there are several the one called `name` is picked.
+/// # Returns
+/// [ExtractError::ModLayout] describing what was found if there isn't exactly one.
+pub fn find_mod_root(extracted: &Path, name: &str) -> Result<PathBuf, ExtractError> {
+    let layout_err = |reason: String| ExtractError::ModLayout { name: name.to_string(), reason };
+
+    let mut found = Vec::new();
+    mod_root_candidates(extracted, 0, &mut found)?;
+    found.sort();
+    let with_cpp: Vec<PathBuf> = found.iter().filter(|d| has_child(d, "mod.cpp", false)).cloned().collect();
+    let mut candidates = match with_cpp.is_empty() {
+        true => found.clone(),
+        false => with_cpp
+    };
+
+    if candidates.len() > 1 {
+        candidates.retain(|d| d.file_name().is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name)));
+        if candidates.len() != 1 {
+            let names: Vec<String> = found.iter().map(|d| d.strip_prefix(extracted).unwrap_or(d).display().to_string()).collect();
+            return Err(layout_err(format!("it contains several mods ({}) and none is called '{}'", names.join(", "), name)));
+        }
+    }
+    match candidates.pop() {
+        Some(root) => {
+            if root.file_name().is_none_or(|n| n.to_string_lossy() != name) {
+                warn!("mod folder for {} is at '{}' in the archive", name, root.strip_prefix(extracted).unwrap_or(&root).display());
+            }
+            Ok(root)
+        }
+        None => {
+            let top: Vec<String> = fs::read_dir(extracted)?.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect();
+            Err(layout_err(format!("no folder contains addons/ (archive contains: {})", top.join(", "))))
+        }
+    }
+}
+
+/// moves `new` to `target`, keeping the old version at `backup` until the swap is done so it can be put back on failure.
+fn swap_into_place(new: &Path, target: &Path, backup: &Path) -> Result<(), Error> {
+    let had_old = target.exists();
+    if had_old {
+        if !target.is_dir() {
+            return Err(anyhow!("refusing to replace '{}' as not a folder", target.display()));
+        }
+        fs::rename(target, backup)?;
+    }
+    if let Err(e) = fs::rename(new, target) {
+        if had_old {
+            fs::rename(backup, target).map_err(|re| anyhow!("failed to install '{}' ({}) and failed to restore the previous version from '{}': {}", target.display(), e, backup.display(), re))?;
+        }
+        return Err(e.into());
+    }
+    if had_old {
+        if let Err(e) = fs::remove_dir_all(backup) {
+            warn!("failed to remove backup {}: {}", backup.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// a previous install stopped mid swap if `backup` is still there. it's the old version of `target`,
+/// only needed if the new one didnt make it, so it's either put back or removed.
+fn recover_backup(target: &Path, backup: &Path) -> Result<(), Error> {
+    if backup.exists() {
+        if target.exists() {
+            remove_path(backup)?;
+        } else {
+            warn!("restoring {} from an interrupted install", target.display());
+            fs::rename(backup, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// moves the entries `names` in `from` into `to` like [swap_into_place], but all or nothing. whatever they replace is moved
+/// to `backup(name)` before anything is moved in, and if any move fails every entry is put back as it was.
+fn swap_all_into_place(from: &Path, to: &Path, names: &[OsString], backup: impl Fn(&OsStr) -> PathBuf) -> Result<(), Error> {
+    let mut backed_up = Vec::new();
+    let mut placed = Vec::new();
+    let ret = (|| {
+        for name in names {
+            let target = to.join(name);
+            if target.exists() {
+                if target.is_dir() != from.join(name).is_dir() {
+                    return Err(anyhow!("refusing to replace '{}' as it's not a {}", target.display(), match target.is_dir() { true => "file", false => "folder" }));
+                }
+                fs::rename(&target, backup(name))?;
+                backed_up.push(name);
+            }
+        }
+        for name in names {
+            fs::rename(from.join(name), to.join(name))?;
+            placed.push(name);
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = ret {
+        //new versions go back to `from` first to make room for the old ones
+        let left: Vec<String> = placed.into_iter().filter(|name| fs::rename(to.join(name), from.join(name)).is_err()).map(|name| to.join(name).display().to_string()).collect();
+        let stranded: Vec<String> = backed_up.into_iter().filter(|name| fs::rename(backup(name), to.join(name)).is_err()).map(|name| backup(name).display().to_string()).collect();
+        let e = match left.is_empty() {
+            true => e,
+            false => e.context(format!("failed to take out the new versions of {}", left.join(", ")))
+        };
+        return match stranded.is_empty() {
+            true => Err(e),
+            false => Err(e.context(format!("failed to put back the previous versions, they're at: {}", stranded.join(", "))))
+        };
+    }
+    for name in backed_up {
+        if let Err(e) = remove_path(&backup(name)) {
+            warn!("failed to remove backup {}: {}", backup(name).display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// extracts an archive into a staging folder `.{name}.staging` inside `dir` and runs `then` on it.
+/// staging next to the destination keeps moves out of it a rename on the same drive.
+/// the staging folder is removed afterwards, even on failure.
+fn with_staging(archive: &Path, dir: &Path, name: &str, password: Option<&str>, progress: Option<&ProgressBar>, cancel: &CancellationToken, then: impl FnOnce(&Path) -> Result<(), Error>) -> Result<(), Error> {
+    let staging = dir.join(format!(".{}.staging", name));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    let ret = (|| {
+        extract_archive(archive, &staging, password, progress, cancel)?;
+        if cancel.is_cancelled() {
+            return Err(ExtractError::Cancelled.into());
+        }
+        then(&staging)
+    })();
+
+    if staging.exists() {
+        if let Err(e) = fs::remove_dir_all(&staging) {
+            warn!("failed to remove staging folder {}: {}", staging.display(), e);
+        }
+    }
+    ret
+}
+
+/// extracts a mod archive into a staging folder inside `mod_dir` and runs `then` on the mod folder found in it.
+fn with_staged_mod(archive: &Path, mod_dir: &Path, name: &str, progress: Option<&ProgressBar>, cancel: &CancellationToken, then: impl FnOnce(&Path) -> Result<(), Error>) -> Result<(), Error> {
+    with_staging(archive, mod_dir, name, None, progress, cancel, |staging| then(&find_mod_root(staging, name)?))
+}
+
+/// extracts a mod archive to a staging folder, then swaps the mod folder found in it into `<mod_dir>/<name>`.
+/// the installed mod is only touched once the new one has extracted and been found, and is restored if the swap fails,
+/// so a failed or cancelled update leaves the previous version in place.
+pub fn install_mod(archive: &Path, mod_dir: &Path, name: &str, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), Error> {
+    let backup = mod_dir.join(format!(".{}.backup", name));
+    let target = mod_dir.join(name);
+    recover_backup(&target, &backup)?;
+    with_staged_mod(archive, mod_dir, name, progress, cancel, |root| swap_into_place(root, &target, &backup))
+}
+
+/// extracts an encrypted dlc archive to a staging folder in `arma_dir`, then moves what's in it into `arma_dir`.
+/// everything already there is swapped out together, see [swap_all_into_place], so a wrong password, a damaged archive,
+/// a cancel or a failed move leaves the arma folder untouched.
+pub fn install_dlc(archive: &Path, arma_dir: &Path, name: &str, password: &str, progress: Option<&ProgressBar>, cancel: &CancellationToken) -> Result<(), Error> {
+    let backup = |entry: &OsStr| arma_dir.join(format!(".{}.backup", entry.to_string_lossy()));
+    with_staging(archive, arma_dir, name, Some(password), progress, cancel, |staging| {
+        let entries = fs::read_dir(staging)?.map(|e| e.map(|e| e.file_name())).collect::<Result<Vec<_>, _>>()?;
+        for entry in &entries {
+            recover_backup(&arma_dir.join(entry), &backup(entry))?;
+        }
+        swap_all_into_place(staging, arma_dir, &entries, backup)
+    })
+}
+
@@ -12,13 +12,17 @@ pub mod msgraph;
 pub mod secrets;
 ///utilities for verifying downloaded mods.
 pub mod dirhash;
+///onedrive's QuickXorHash, for checking downloads against the hash msgraph reports.
+pub mod quickxor;
 ///for handling of rendering the terminal UI.
 pub mod UI;
 pub mod servers;
 pub mod configs;
 pub mod download;
+///in-process extraction of .7z and .zip archives, with the bundled 7za as a fallback.
+pub mod extract;
 
-use std::{default, env, fmt::Debug, fs::{remove_file, File}, io::{BufRead, BufReader, Read, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, str::FromStr, sync::{Arc, Mutex}, time::{Duration, SystemTime}, usize};
+use std::{collections::HashMap, default, env, fmt::Debug, fs::{remove_file, File}, io::{BufRead, BufReader, Read, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, str::FromStr, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime}, usize};
 use anyhow::{anyhow,Error};
 use base64::display;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -48,6 +52,7 @@ pub const TITLE: &str = "CAC Launcher";
 const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:135.0) Gecko/20100101 Firefox/135.0";
 pub const PROGRESS_STYLE_DOWNLOAD: &str = "{spinner} {msg:.green.bold} {percent}% {decimal_bytes}/{decimal_total_bytes} [{decimal_bytes_per_sec}], Elapsed: {elapsed}, ETA: {eta}";
 pub const PROGRESS_STYLE_EXTRACT: &str = "{spinner} Extracting: {percent}% Elapsed: {elapsed}, ETA: {eta} {msg:.green.bold}";
+pub const PROGRESS_STYLE_HASH: &str = "{spinner} Verifying: {percent}% {decimal_bytes}/{decimal_total_bytes} Elapsed: {elapsed}, ETA: {eta} {msg:.green.bold}";
 pub const PROGRESS_STYLE_MESSAGE: &str = "{spinner} {msg:.green.bold}";
 
 pub static Z7_EXE: &[u8] = include_bytes!("7za.exe");
@@ -89,8 +94,8 @@ pub struct DownloadInfo {
         pub etag: String,
 }
 
-pub async fn final_url(client: Client, url: Url) -> Result<Url, Error> {
-    let response = client.get(url).timeout(TIMEOUT).send().await?;
+pub async fn final_url(ctx: &ClientCtx, url: Url) -> Result<Url, Error> {
+    let response = ctx.send(ctx.client.get(url).timeout(TIMEOUT), None).await?;
     Ok(response.url().clone())
 }
 
@@ -143,9 +148,94 @@ pub async fn get_download_info(ctx: &ClientCtx,url: Url,dir: &Path) -> Result<Do
     return Ok(DownloadInfo{etag: etag, sessionUrl: url.clone(), downloadUrl: download_url, filename: filename, fileSize: file_size})
 }
 
+/// how long to pause a host that throttled us without saying how long for.
+const DEFAULT_THROTTLE_WAIT: Duration = Duration::from_secs(10);
+/// how many times a request is resent after being throttled before giving up and returning the throttled response.
+const THROTTLE_MAX_RETRIES: u32 = 10;
+
+/// parses a [Retry-After](https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Retry-After) header, given in either seconds or as a HTTP date.
+pub fn retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+    let v = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = v.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(v).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// hosts that have asked us to back off. shared by every request sent through a `ClientCtx`, so when one request is throttled
+/// all others to the same host wait too.
+/// [msgraph reference](https://learn.microsoft.com/en-us/graph/throttling)
+#[derive(Default, Debug)]
+pub struct Throttle {
+    paused: Mutex<HashMap<String, Instant>>,
+}
+
+impl Throttle {
+    /// time left before requests to `host` can resume, or None if it isn't paused.
+    pub fn remaining(&self, host: &str) -> Option<Duration> {
+        let lock = self.paused.lock().unwrap();
+        let until = lock.get(host)?;
+        until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    }
+
+    /// pause requests to `host` for `wait`. an existing longer pause is kept.
+    pub fn pause(&self, host: &str, wait: Duration) {
+        let until = Instant::now() + wait;
+        let mut lock = self.paused.lock().unwrap();
+        let e = lock.entry(host.to_string()).or_insert(until);
+        if *e < until {
+            *e = until;
+        }
+    }
+
+    /// reads throttling headers from a response, pausing `host` if asked to.
+    /// a 429 / 503 response pauses for its Retry-After, and sharepoint's RateLimit-* headers pause pre-emptively once the remaining quota hits 0.
+    /// # Returns
+    /// true if the request was rejected due to throttling and should be resent.
+    pub fn observe(&self, host: &str, response: &Response) -> bool {
+        let status = response.status();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            let wait = retry_after(response.headers()).unwrap_or(DEFAULT_THROTTLE_WAIT);
+            warn!("{} throttled request ({}), pausing for {}s",host,status.as_str(),wait.as_secs());
+            self.pause(host, wait);
+            return true;
+        }
+        let header_u64 = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse::<u64>().ok());
+        if let (Some(0), Some(reset)) = (header_u64("ratelimit-remaining"), header_u64("ratelimit-reset")) {
+            warn!("{} rate limit reached, pausing for {}s",host,reset);
+            self.pause(host, Duration::from_secs(reset));
+        }
+        false
+    }
+
+    /// waits until requests to `host` can resume, taking the time waited off `budget`. shows the time left on `progress` whilst waiting.
+    /// # Returns
+    /// false, without waiting any longer, once the pause would outlast `budget`.
+    pub async fn wait(&self, host: &str, progress: Option<&ProgressBar>, budget: &mut Duration) -> Result<bool,Error> {
+        let start = Instant::now();
+        let limit = *budget;
+        while let Some(left) = self.remaining(host) {
+            *budget = limit.saturating_sub(start.elapsed());
+            if left > *budget {
+                return Ok(false);
+            }
+            if let Some(progress) = progress {
+                progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_MESSAGE)?);
+                progress.set_message(format!(" {} is throttling requests, resuming in {}s... ",host,left.as_secs()+1));
+            }
+            tokio::time::sleep(left.min(Duration::from_secs(1))).await;
+        }
+        *budget = limit.saturating_sub(start.elapsed());
+        Ok(true)
+    }
+}
+
+#[derive(Clone)]
 pub struct ClientCtx {
     pub client: Client,
-    pub jar: Arc<CookieStoreRwLock>
+    pub jar: Arc<CookieStoreRwLock>,
+    pub throttle: Arc<Throttle>,
 }
 
 impl ClientCtx {
@@ -165,37 +255,68 @@ impl ClientCtx {
             .connect_timeout(TIMEOUT)
             .pool_idle_timeout(TIMEOUT)
             .build()?,
-            jar: jar
+            jar: jar,
+            throttle: Arc::new(Throttle::default()),
         };
         Ok(clientCtx)
     }
-}
 
-/// Given a vec of drive items, will group partial archives by their full archive name (without the .nnn extension). Single archives are just a vec of one element
-pub fn group_drive_item_archives(drive_items: Vec<SharedDriveItem>) -> Result<Vec<(String, Vec<SharedDriveItem>)>,Error> {
-    let mut items: Vec<(String, Vec<SharedDriveItem>)> = Vec::new();
-
-    for i in drive_items {
-        let (basename, ext) = i.name.rsplit_once('.').ok_or(anyhow!("failed to rsplit filename to get extension"))?;
-        let re_partial_ext = Regex::new("^[0-9]{3}$")?;
-        if re_partial_ext.is_match(ext){
-            //partial archive grouping 
-            match items.iter().position(|j| {j.0 == basename}){
-                None => {
-                    items.push((basename.to_string(),vec!(i)));
-                }
-                Some(idx) => {
-                    items[idx].1.push(i);
-                }
+    /// sends a request built from `self.client`, first waiting out any throttling of its host.
+    /// throttled responses pause every request to the host and are resent once the pause ends, waiting is shown on `progress`.
+    pub async fn send(&self, request: reqwest::RequestBuilder, progress: Option<&ProgressBar>) -> Result<Response,Error> {
+        self.send_within(request, progress, &mut Duration::MAX).await
+    }
+
+    /// like [ClientCtx::send], but waits no longer than `budget` in total for throttling, taking the time waited off it.
+    /// once the next wait would outlast it the last throttled response is returned, or [download::DownloadError::Throttled]
+    /// if the host was already paused for longer before sending.
+    pub async fn send_within(&self, request: reqwest::RequestBuilder, progress: Option<&ProgressBar>, budget: &mut Duration) -> Result<Response,Error> {
+        let request = request.build()?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let mut attempt = 0;
+        let mut throttled = None;
+        loop {
+            if !self.throttle.wait(&host, progress, budget).await? {
+                return match throttled {
+                    Some(response) => Ok(response),
+                    None => {
+                        let wait = self.throttle.remaining(&host).unwrap_or_default();
+                        Err(download::DownloadError::Throttled { host, wait }.into())
+                    }
+                };
+            }
+            let req = request.try_clone().ok_or(anyhow!("request to {} can't be resent",host))?;
+            let response = self.client.execute(req).await?;
+            if !self.throttle.observe(&host, &response) || attempt >= THROTTLE_MAX_RETRIES {
+                return Ok(response);
             }
-        }else{
-            items.push((basename.to_string(),vec!(i)));
+            throttled = Some(response);
+            attempt += 1;
         }
     }
-    Ok(items)
 }
 
-pub fn unzip(fname: &str,dest: &str, mut o_progress: Option<&mut ProgressBar>) -> Result<(),Error> {
+/// Given a vec of drive items, will group partial archives by their full archive name (without the .nnn or .partN extension). Single archives are just a vec of one element.
+/// parts are sorted by volume number, see [extract::group_volumes].
+pub fn group_drive_item_archives(drive_items: Vec<SharedDriveItem>) -> Result<Vec<(String, Vec<SharedDriveItem>)>,Error> {
+    Ok(extract::group_volumes(drive_items, |i| i.name.as_str())?)
+}
+
+/// unpacks the bundled 7za.exe into the system temp folder the first time it's needed.
+fn z7_exe() -> Result<PathBuf,Error> {
+    static Z7_PATH: once_cell::sync::OnceCell<PathBuf> = once_cell::sync::OnceCell::new();
+    Z7_PATH.get_or_try_init(|| {
+        let path = env::temp_dir().join("cac-launcher-7za.exe");
+        //another launcher may be running it already, reuse it rather than overwriting
+        if std::fs::metadata(&path).map(|m| m.len() != Z7_EXE.len() as u64).unwrap_or(true) {
+            std::fs::write(&path, Z7_EXE).map_err(|e| anyhow!("failed to unpack 7za.exe: {}",e))?;
+        }
+        Ok(path)
+    }).cloned()
+}
+
+/// extracts using the bundled 7za, see [extract::extract_archive] which prefers the native backend.
+pub fn unzip(fname: &str,dest: &str, password: Option<&str>, mut o_progress: Option<&mut ProgressBar>) -> Result<(),Error> {
     match o_progress {
         None => {},
         Some(ref progress) => {
@@ -209,17 +330,20 @@ pub fn unzip(fname: &str,dest: &str, mut o_progress: Option<&mut ProgressBar>) -
     let mut z7_stderr_log: Vec<u8> = Vec::new();
 
     let o_arg = format!("-o{}",dest);
+    //always pass -p so 7za never stops to prompt for one
+    let p_arg = format!("-p{}",password.unwrap_or(""));
     let args = [
             "e",
             "-y",
             o_arg.as_str(),
+            p_arg.as_str(),
             "-sccUTF-8",
             "-slp",
             "-spf",
             "-bsp2", //ask 7zip to print progress to stderr
             fname,
         ];
-        let mut z7_run = Command::new("./7za.exe").args(args)
+        let mut z7_run = Command::new(z7_exe()?).args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn().map_err(|e| anyhow!("failed to start 7zip: {}",e))?;
@@ -273,9 +397,9 @@ pub fn unzip(fname: &str,dest: &str, mut o_progress: Option<&mut ProgressBar>) -
             let mut z7log = String::new();
             reader.read_to_string(&mut z7log)?;
             let mut f = std::fs::File::create("7z.log")?;
-            f.write(z7log.as_bytes())?;
+            f.write_all(z7log.as_bytes())?;
             
-            f.write(&z7_stderr_log)?;
+            f.write_all(&z7_stderr_log)?;
 
             return Err(anyhow!("failed to extract {} (see 7z.log)",fname));
         }
@@ -10,11 +10,11 @@ use anyhow::{anyhow,Error};
 use log::{log, warn};
 use ratatui::{style::Stylize, text::Line};
 use regex::Regex;
-use reqwest::{header::CONTENT_DISPOSITION};
 use simplelog::{WriteLogger};
 use src_backend::{configs::{Config, *},UI::{self, TUI}, *};
 use tokio::time::{sleep, Sleep};
-use clap::Parser;
+use tokio_util::sync::CancellationToken;
+use clap::{Parser, Subcommand};
 
 static CONFIG_URL: &str = "https://github.com/Benkol003/CAC-Config/archive/master.zip";
 
@@ -23,44 +23,49 @@ static CONFIG_URL: &str = "https://github.com/Benkol003/CAC-Config/archive/maste
 /// if no config files exist locally then will create them from defaults.
 async fn update_cac_config(tui: &mut TUI) -> Result<(),Error> {
 
-    if !TMP_DOWNLOADS_FILE.as_path().is_file() {
-        let tmp_manifest = CACDownloadManifest::default();
-        tmp_manifest.save()?;
-    }
-    //TODO clean out tmp folder
+    CACDownloadManifest::update_or_default(|m| m.clean(TMP_FOLDER.as_path()))?;
 
     tui.popup_message("fetching latest configuration...");
 
     let ctx = ClientCtx::build()?;
-    let response = ctx.client.get(CONFIG_URL).timeout(TIMEOUT).send().await?;
+    let response = ctx.send(ctx.client.get(CONFIG_URL).timeout(TIMEOUT), None).await?;
 
     if !response.status().is_success() {
         return Err(anyhow!("download URL HTTP error: {}",response.status().as_str()));
     }
 
-    let headers = response.headers().clone();
-    let fname = headers.get(CONTENT_DISPOSITION).ok_or(anyhow!("missing CONTENT_DISPOSITION header from file download"))?.to_str()?
-    .rsplit_once("filename=").ok_or(anyhow!("rsplit failed"))?.1;
-    let fpath = TMP_FOLDER.join(fname);
+    let fname = download::response_filename(response.headers(), response.url())?;
+    let fpath = TMP_FOLDER.join(&fname);
     {   
         let data = response.bytes().await?;
         let mut file = File::create(&fpath)?;
         file.write_all(&data)?;
     }
-    unzip(fpath.to_str().unwrap(),TMP_FOLDER.to_str().unwrap(),None)?;
+    extract::extract_archive(&fpath,&TMP_FOLDER,None,None,&CancellationToken::new())?;
     fs::remove_file(fpath)?;
 
     //path to extracted folder
     let regex = Regex::new(r#"^(.*?)\.(?:zip|7z)(?:\.\d{3})?$"#).unwrap();
-    let folder_path = TMP_FOLDER.join(regex.captures(fname).unwrap().get(1).unwrap().as_str().to_string());
+    let folder_path = TMP_FOLDER.join(regex.captures(&fname).unwrap().get(1).unwrap().as_str());
 
     let new_content = CACContent::read_from(folder_path.join("content.json"))?;
 
-    let mut config= match CONFIG_FILE.as_path().is_file() {
+    let config= match CONFIG_FILE.as_path().is_file() {
         false => {
             let config = CACConfig::default(tui)?;
-            config.save()?;
-            config
+            //another launcher may have made one whilst asking
+            let fresh = {
+                let _lock = CACConfig::lock()?;
+                let fresh = !CONFIG_FILE.is_file();
+                if fresh {
+                    config.save()?;
+                }
+                fresh
+            };
+            match fresh {
+                true => config,
+                false => CACConfig::read()?
+            }
         },
         true => {
             CACConfig::read()?
@@ -73,7 +78,8 @@ async fn update_cac_config(tui: &mut TUI) -> Result<(),Error> {
             CACContent::read()?
         }
         false => {
-            new_content.save_to(CONTENT_FILE.as_path())?;
+            let _lock = CACContent::lock()?;
+            new_content.save()?;
             tui.warn_unknown_mod_state();
             return Ok(());
         }
@@ -81,24 +87,38 @@ async fn update_cac_config(tui: &mut TUI) -> Result<(),Error> {
 
     // find changed stuff
     // TODO well you want to flatten the contents aswell to just name:link pairs
-    //TODO might want atomic writes and also always truncate everything over
+    let mut changed = Vec::new();
     new_content.content_iter().for_each(|nm|{
         let om = old_content.content_map();
         if !om.contains_key(nm.0){
-            config.pending_updates.insert(nm.0.clone());
+            changed.push(nm.0.clone());
         }else {
             let ol = om.get(nm.0).unwrap();
             if (*ol)!=nm.1{
-                config.pending_updates.insert(nm.0.clone());
+                changed.push(nm.0.clone());
             }   
             
         }
     });
 
-    config.save()?;
+    CACConfig::update(|c| {
+        c.pending_updates.extend(changed);
+        Ok(())
+    })?;
 
-    fs::copy(folder_path.join("content.json"), CONTENT_FILE.as_path())?;
-    fs::copy(folder_path.join("servers.json"), SERVERS_FILE.as_path())?;
+    //replaced outright, so just kept from interleaving with anyone else's save
+    {
+        let _lock = CACContent::lock()?;
+        new_content.save()?;
+    }
+    let servers = servers::ServerList::read_from(folder_path.join("servers.json"))?;
+    {
+        let _lock = servers::ServerList::lock()?;
+        servers.save()?;
+    }
+    if folder_path.join("mods-manifest.json").is_file() {
+        fs::copy(folder_path.join("mods-manifest.json"), MODS_MANIFEST_FILE.as_path())?;
+    }
     fs::remove_dir_all(folder_path)?;
 
     Ok(())
@@ -123,24 +143,96 @@ fn panic_handler(info: &PanicHookInfo) {
 #[command(version, about)]
 struct Args{
     #[arg(long,default_value_t = false, help="don't update the local CAC-Config manifest with a downloaded latest version")]
-    no_update: bool
+    no_update: bool,
+
+    #[arg(long, help="folder to keep the launcher's config in, instead of $CAC_LAUNCHER_CONFIG_DIR, a portable install or the platform's config folder")]
+    config_dir: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>
+}
+
+#[derive(Subcommand,Debug)]
+enum Command {
+    /// check installed mods against the published mod manifest, and queue any that don't match for update
+    Verify {
+        #[arg(long,default_value_t = false, help="rehash every file instead of trusting unchanged sizes and timestamps")]
+        force: bool,
+
+        #[arg(long,default_value_t = false, help="download just the files that don't match and fix the mods in place")]
+        repair: bool
+    }
+}
+
+/// runs `verify` from the command line without the TUI, using the local copy of the published manifest.
+/// # Returns
+/// whether all mods matched, or were repaired if `repair` is set.
+async fn verify_cli(force: bool, repair: bool) -> Result<bool,Error> {
+    let config = CACConfig::read().map_err(|e| anyhow!("failed to read {}, run the launcher first: {}",CONFIG_FILE.display(),e))?;
+    println!("verifying mods in {}...",config.absolute_mod_dir()?.display());
+    let bar = indicatif::ProgressBar::new(0);
+    let progress = dirhash::HashProgress::new(Some(bar.clone()));
+    let report = dirhash::verify_installed_mods(&config, force, &progress, &CancellationToken::new());
+    bar.finish_and_clear();
+    let report = report?;
+    println!("hashed {} files",progress.files().1);
+
+    for (name, diff) in &report {
+        if diff.is_empty() {
+            println!("{}: ok",name);
+            continue;
+        }
+        println!("{}: queued for update",name);
+        for line in diff.report(usize::MAX) {
+            println!("    {}",line);
+        }
+    }
+    let bad: std::collections::BTreeMap<_,_> = report.into_iter().filter(|(_,d)| !d.is_empty()).collect();
+    println!("{} mods need updating",bad.len());
+    if bad.is_empty() || !repair {
+        return Ok(bad.is_empty());
+    }
+
+    println!("repairing {} mods...",bad.len());
+    let multi = indicatif::MultiProgress::new();
+    let bars = (0..config.max_concurrent_downloads.max(1)).map(|_| multi.add(indicatif::ProgressBar::new(1))).collect();
+    let extract = multi.add(indicatif::ProgressBar::new(1));
+    let title = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+    let done = download::repair_items(bad, bars, extract, title, CancellationToken::new()).await?;
+    println!("{}",if done {"repaired"} else {"repair cancelled"});
+    Ok(done)
 }
 
 #[tokio::main]
 async fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
+    let args = Args::parse();
+    if let Some(dir) = args.config_dir.clone() {
+        configs::set_config_dir(dir).unwrap();
+    }
 
     //TODO fails if file exists as symlink/file
     if !std::fs::exists(CONFIG_FOLDER.as_path()).unwrap() {
-        std::fs::create_dir(CONFIG_FOLDER.as_path()).unwrap();
+        std::fs::create_dir_all(CONFIG_FOLDER.as_path()).unwrap();
     }
 
     WriteLogger::init(simplelog::LevelFilter::Warn, simplelog::Config::default(), File::create(LOG_PATH.as_path()).unwrap()).unwrap();
+
+    if let Some(Command::Verify { force, repair }) = args.command {
+        match verify_cli(force, repair).await {
+            Ok(ok) => std::process::exit(if ok {0} else {1}),
+            Err(e) => {
+                eprintln!("verification failed: {}",e);
+                std::process::exit(-1);
+            }
+        }
+    }
+
     let mut tui = TUI::new();
 
     std::panic::set_hook(Box::new(panic_handler));
 
-    match fake_main(&mut tui).await {
+    match fake_main(&mut tui, args).await {
     Ok(_) => {},
     Err(e) => {
         let bt = e.backtrace();
@@ -154,20 +246,12 @@ async fn main() {
     };
 }
 
-async fn fake_main(tui: &mut TUI) -> Result<(), Error> {
-
-    let args = Args::parse();
+async fn fake_main(tui: &mut TUI, args: Args) -> Result<(), Error> {
 
     force_create_dir(&CONFIG_FOLDER)?;
     force_create_dir(&CONFIG_FOLDER.join("tmp"))?;
+    tui.recover_configs()?;
 
-    //unpack 7z to use for the rest of the program.
-    let _z7 = FileAutoDeleter::new("7za.exe"); //allows file to be deleted automatically even if theres an error
-    { //scope so file is closed before running process
-        let mut z7 = File::create("7za.exe")?;
-        z7.write_all(Z7_EXE).map_err(|_| anyhow!("failed to unpack 7za.exe"))?;
-    }
-    
     if !args.no_update {
         update_cac_config(tui).await?;
     } 
@@ -20,6 +20,7 @@ use std::{
     io::{Read, Seek, Write},
     path::{Path, PathBuf},
     pin::{self, Pin},
+    sync::Mutex,
     task::Context,
     time::Duration,
 };
@@ -47,8 +48,8 @@ use reqwest::{
     Client, Request, Response, StatusCode, Url, Version,
 };
 
-use crate::download::download_file;
-use crate::{PROGRESS_STYLE_DOWNLOAD, TIMEOUT, final_url, secrets};
+use crate::download::{download_file, RetryPolicy};
+use crate::{ClientCtx, configs::CACDownloadManifest, PROGRESS_STYLE_DOWNLOAD, TIMEOUT, final_url, secrets};
 
 const TENANT_ID: &str = "4fd01353-8fd7-4a18-a3a1-7cd70f528afa";
 const APP_CLIENT_ID: &str = "9ecaa0e8-9caf-4f49-94e8-8430bbf57486";
@@ -111,8 +112,8 @@ pub enum FsEntryType {
 }
 
 /// [msgraph reference](https://learn.microsoft.com/en-us/graph/api/shares-get?view=graph-rest-1.0&tabs=http)
-async fn get_encoded_sharing_url(client: &Client, url: Url) -> Result<String, Error> {
-    let final_url = final_url(client.clone(),url).await?.to_string();
+async fn get_encoded_sharing_url(ctx: &ClientCtx, url: Url) -> Result<String, Error> {
+    let final_url = final_url(ctx,url).await?.to_string();
     warn!("final url: {}",final_url);
     return Ok(format!("u!{}", BASE64_URL_SAFE_NO_PAD.encode(final_url)));
 }
@@ -181,24 +182,24 @@ pub fn is_sharepoint_link(url: &str) -> Result<bool, Error> {
 }
 
 /// [msgraph reference](https://learn.microsoft.com/en-us/graph/api/shares-get?view=graph-rest-1.0&tabs=http)
+/// if graph throttles the request, waits and retries, showing the wait on `progress`.
 pub async fn get_shared_drive_item(
-    client: Client,
+    ctx: ClientCtx,
     token: String,
     url: Url,
+    progress: Option<&ProgressBar>,
 ) -> Result<SharedDriveItem, MsGraphError> {
-    let client = reqwest::Client::new(); //TODO use client ctx instead
     //let mut params = HashMap::new();
 
-    let share_id = get_encoded_sharing_url(&client, url).await?;
+    let share_id = get_encoded_sharing_url(&ctx, url).await?;
     let mut headers = HeaderMap::new();
     headers.append(header::AUTHORIZATION, format!("Bearer {}", token).parse()?);
     headers.append(header::CONTENT_TYPE, "application/json".parse()?);
     headers.append("prefer", "redeemSharingLink".parse()?);
-    let mut response = client
+    let mut response = ctx.send(ctx.client
         .get(format!("{}shares/{}/driveItem", MSAPI_URL, share_id))
         .headers(headers)
-        .timeout(TIMEOUT)
-        .send()
+        .timeout(TIMEOUT), progress)
         .await?;
     if response.status().as_u16() != 200 {
         return Err(anyhow!(
@@ -224,14 +225,18 @@ pub struct DownloadRequest {
 /// [msgraph reference](https://learn.microsoft.com/en-us/graph/api/driveitem-get-content?view=graph-rest-1.0&tabs=http)
 /// # Returns
 /// path to the temporary file downloaded, or None if cancelled, or an Error.
-/// downloads will be resumed later after a cancel if you attempt to download the same drive item to the same destination folder.
+/// downloads will be resumed later after a cancel if you attempt to download the same drive item to the same destination folder
+/// (and it hasnt changed since, if tracked in `manifest`).
+/// the file is checked against the item's quickXorHash before returning.
 /// TODO: if there is a folder with the same name as the download file...
 pub async fn download_item(
-    client: Client,
+    ctx: ClientCtx,
     token: String,
     item: SharedDriveItem,
     dest_folder: String,
     progress: &mut ProgressBar,
+    manifest: Option<&Mutex<CACDownloadManifest>>,
+    retry: RetryPolicy,
     cancel: CancellationToken,
 ) -> Result<Option<PathBuf>, Error> {
     progress.set_style(ProgressStyle::with_template(PROGRESS_STYLE_DOWNLOAD)?);
@@ -247,7 +252,11 @@ pub async fn download_item(
     let dest_url = Url::parse(format!("{}shares/{}/driveItem/content",MSAPI_URL, item.share_id).as_str())?;
 
     
-    download_file(client,item.name,dest_url,Some(headers),dest_folder,progress,item.id.as_str(),cancel).await
+    let quick_xor_hash = match &item.item {
+        FsEntryType::File { hashes } => Some(hashes.quick_xor_hash.as_str()),
+        FsEntryType::Folder { .. } => None,
+    };
+    download_file(ctx,item.name.clone(),dest_url,Some(headers),dest_folder,None,progress,item.id.as_str(),quick_xor_hash,manifest,retry,cancel).await
 }
 
 ///[msgraph reference](https://login.microsoftonline.com/{TENANT_ID}/oauth2/v2.0/token)\
@@ -0,0 +1,73 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+const WIDTH_IN_BITS: usize = 160;
+const SHIFT: usize = 11;
+const CELLS: usize = (WIDTH_IN_BITS - 1) / 64 + 1;
+pub const HASH_LEN: usize = (WIDTH_IN_BITS - 1) / 8 + 1;
+
+/// streaming [QuickXorHash](https://learn.microsoft.com/en-us/onedrive/developer/code-snippets/quickxorhash).
+/// port of microsoft's reference implementation. feed data in with `update` in chunks of any size.
+#[derive(Clone, Debug, Default)]
+pub struct QuickXorHash {
+    data: [u64; CELLS],
+    shift_so_far: usize,
+    length_so_far: u64,
+}
+
+impl QuickXorHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let mut vector_index = self.shift_so_far / 64;
+        let mut vector_offset = self.shift_so_far % 64;
+
+        for i in 0..buf.len().min(WIDTH_IN_BITS) {
+            let is_last_cell = vector_index == CELLS - 1;
+            let bits_in_cell = if is_last_cell { WIDTH_IN_BITS % 64 } else { 64 };
+
+            //every WIDTH_IN_BITS'th byte from i lands on the same bit offset, so xor them together first
+            let xored = buf[i..].iter().step_by(WIDTH_IN_BITS).fold(0u8, |acc, b| acc ^ b);
+            if vector_offset <= bits_in_cell - 8 {
+                self.data[vector_index] ^= (xored as u64) << vector_offset;
+            } else {
+                //byte straddles two cells
+                let next = if is_last_cell { 0 } else { vector_index + 1 };
+                let low = bits_in_cell - vector_offset;
+                self.data[vector_index] ^= (xored as u64) << vector_offset;
+                self.data[next] ^= (xored as u64) >> low;
+            }
+
+            vector_offset += SHIFT;
+            while vector_offset >= bits_in_cell {
+                vector_index = if is_last_cell { 0 } else { vector_index + 1 };
+                vector_offset -= bits_in_cell;
+            }
+        }
+
+        self.shift_so_far = (self.shift_so_far + SHIFT * (buf.len() % WIDTH_IN_BITS)) % WIDTH_IN_BITS;
+        self.length_so_far += buf.len() as u64;
+    }
+
+    pub fn finalize(&self) -> [u8; HASH_LEN] {
+        let mut ret = [0u8; HASH_LEN];
+        for (i, cell) in self.data.iter().enumerate() {
+            let bytes = cell.to_le_bytes();
+            let start = i * 8;
+            let n = (HASH_LEN - start).min(8);
+            ret[start..start + n].copy_from_slice(&bytes[..n]);
+        }
+        //xor the length into the least significant bits
+        let len = self.length_so_far.to_le_bytes();
+        for (i, b) in len.iter().enumerate() {
+            ret[WIDTH_IN_BITS / 8 - len.len() + i] ^= b;
+        }
+        ret
+    }
+
+    /// the hash base64 encoded, as msgraph reports it in `hashes.quickXorHash`.
+    pub fn finalize_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.finalize())
+    }
+}
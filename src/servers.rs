@@ -1,12 +1,12 @@
 use std::{collections::HashMap, fs::DirEntry, path::{self, PathBuf}, time::Duration};
 
 use anyhow::{anyhow};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use a2s::{A2SClient,info::Info};
 use tokio::task::JoinHandle;
 use crate::{configs::{Config, *}, *};
 
-#[derive(Deserialize,Debug)]
+#[derive(Serialize,Deserialize,Debug,Clone)]
 pub struct Server {
     pub address: String,
     pub port: u16,
@@ -22,21 +22,23 @@ const LAUNCH_ARGS: Lazy<Vec<String>> = Lazy::new(|| {
 
 
 impl Server {
+    /// starts arma connected to this server, as the active profile.
     pub fn launch(&self) -> Result<(),Error> {
         let config = CACConfig::read()?;
+        let profile = config.profile()?;
         let mod_dir = config.absolute_mod_dir()?;
         let mut args = LAUNCH_ARGS.clone();
         args.push(format!("-connect={}",self.address));
         args.push(format!("-port={}",self.port));
-        args.push(format!(r#"-name="{}""#,config.username));
+        args.push(format!(r#"-name="{}""#,profile.username));
 
         let mut mod_arg: String = r#"-mod="#.into();
         let opt_mod_iter: Box<dyn Iterator<Item = &String>> = if config.optionals_on {
             Box::new(std::iter::empty::<&String>().into_iter())
         }else {
-            Box::new(config.enabled_optionals.iter())
+            Box::new(profile.enabled_optionals.iter())
         };
-        self.mods.iter().chain(config.enabled_optionals.iter()).chain(opt_mod_iter).for_each(|x|{
+        self.mods.iter().chain(profile.enabled_optionals.iter()).chain(opt_mod_iter).for_each(|x|{
             mod_arg+="\""; 
             if(x.chars().nth(0).unwrap()=='@'){
                 mod_arg+=mod_dir.join(x).as_os_str().to_str().unwrap();
@@ -47,7 +49,7 @@ impl Server {
         });
         args.push(mod_arg);
         if self.password {
-            args.push(format!(r#"-password="{}""#,config.server_password));
+            args.push(format!(r#"-password="{}""#,profile.server_password));
         }
         let args_expanded  =args.iter().fold(String::new(),|i,x|{i+" "+x});
         log::warn!("launching arma 3 with args (len {}): '{}'",args_expanded.len(),args_expanded); //TODO RM 
@@ -56,15 +58,24 @@ impl Server {
     }
 }
 
+/// servers.json, the servers to show keyed by name.
+#[derive(Serialize,Deserialize,Debug,Clone,Default)]
+pub struct ServerList(pub HashMap<String,Server>);
+
+impl Config for ServerList {
+    fn file_path() -> PathBuf {
+        SERVERS_FILE.to_path_buf()
+    }
+
+    const BACKUPS: usize = 3;
+}
+
 pub fn read_config() -> Result<Vec<(String,Server)>, Error> {
     let conf_path = SERVERS_FILE.to_path_buf();
     if !std::fs::exists(&conf_path)? {
         return Err(anyhow!("servers.json config file not found"));
     }
-    let mut file = File::open(&conf_path)?;
-    let mut content = String::new();
-    file.read_to_string(&mut content);
-    Ok(serde_json::from_str::<HashMap<String,Server>>(&content)?.into_iter().collect())
+    Ok(ServerList::read()?.0.into_iter().collect())
 }
 
 /// if we fail to get info about a server, we assume its offline and return None.
@@ -131,8 +142,10 @@ pub fn update_list() -> Result<Vec<(String,Vec<String>)>,Error> {
                 }
             }
         }).chain(
+            //missing ones are already listed above
             server.mods.iter().filter_map(|x|{
-                match config.pending_updates.contains(x) {
+                let present = if x.starts_with("@") {mods_present.contains(x)} else {arma_folders.contains(x)};
+                match present && config.pending_updates.contains(x) {
                     true => {
                         Some(x.clone())
                     }
@@ -144,4 +157,51 @@ pub fn update_list() -> Result<Vec<(String,Vec<String>)>,Error> {
     }).collect();
     Ok(ret)
     
-}
\ No newline at end of file
+}
+
+/// state of an installed content item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModStatus {
+    UpToDate,
+    PendingUpdate,
+    Missing,
+    /// didn't match the published manifest when last verified
+    Corrupt,
+}
+
+impl std::fmt::Display for ModStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UpToDate => "up to date",
+            Self::PendingUpdate => "pending update",
+            Self::Missing => "missing",
+            Self::Corrupt => "corrupt",
+        })
+    }
+}
+
+/// # Returns
+/// every item in `content` with its status, sorted by name. dlc is looked for in the arma folder, everything else in the mods folder.
+/// items in the config's `failed_verification` only count as corrupt while they're still pending an update.
+pub fn content_status(config: &CACConfig, content: &CACContent) -> Result<Vec<(String,ModStatus)>,Error> {
+    let mod_dir = config.absolute_mod_dir()?;
+    let arma_dir = config.arma_dir()?;
+    let mut ret: Vec<_> = content.content_iter().map(|(name,_)| {
+        let dir = match content.dlc.contains_key(name) {
+            true => arma_dir.join(name),
+            false => mod_dir.join(name)
+        };
+        let status = if !dir.is_dir() {
+            ModStatus::Missing
+        } else if !config.pending_updates.contains(name) {
+            ModStatus::UpToDate
+        } else if config.failed_verification.contains(name) {
+            ModStatus::Corrupt
+        } else {
+            ModStatus::PendingUpdate
+        };
+        (name.clone(),status)
+    }).collect();
+    ret.sort_by(|a,b| a.0.cmp(&b.0));
+    Ok(ret)
+}
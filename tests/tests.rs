@@ -27,7 +27,7 @@ mod tests {
 
     //     let client_ctx = ClientCtx::build()?;
     //     let token = msgraph::login(&client_ctx.client).await?;
-    //     let item = msgraph::get_shared_drive_item(client_ctx.client.clone(), token.clone(), url).await?;
+    //     let item = msgraph::get_shared_drive_item(client_ctx.clone(), token.clone(), url, None).await?;
     //     if let FsEntryType::File { hashes: _ } = item.item {
     //         panic!("shared drive item is file not folder");
     //     }
@@ -46,14 +46,39 @@ mod tests {
         Ok(t)
     }
 
+    /// a folder for one test in [tmp_dir], removed again when dropped.
+    struct TestDir(PathBuf);
+
+    impl std::ops::Deref for TestDir {
+        type Target = PathBuf;
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// # Returns
+    /// a fresh absolute folder for a test, clearing anything left from a previous run.
+    fn test_dir(name: &str) -> Result<TestDir,std::io::Error> {
+        let dir = std::path::absolute(PathBuf::from(tmp_dir()?).join(name))?;
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(TestDir(dir))
+    }
+
     #[tokio::test]
     async fn msgraph_download() -> Result<(), Error> {
         let client_ctx = ClientCtx::build()?;
         let token = msgraph::login(&client_ctx.client).await?;
         let url=Url::parse(MOD_LINK)?;
-        let item = msgraph::get_shared_drive_item(client_ctx.client.clone(), token.clone(), url).await?;
+        let item = msgraph::get_shared_drive_item(client_ctx.clone(), token.clone(), url, None).await?;
         let mut bar =ProgressBar::new(0);
-        msgraph::download_item(client_ctx.client.clone(), token.clone(),item.clone(),tmp_dir()?.to_string(),&mut bar, CancellationToken::new()).await?;
+        msgraph::download_item(client_ctx.clone(), token.clone(),item.clone(),tmp_dir()?.to_string(),&mut bar, None, download::RetryPolicy::default(), CancellationToken::new()).await?;
         Ok(())
     }
 
@@ -67,7 +92,7 @@ mod tests {
         println!("final url: {}", new_url);
 
         let token = msgraph::login(&client_ctx.client).await?;
-        let item = msgraph::get_shared_drive_item(client_ctx.client.clone(), token.clone(), new_url.clone()).await?;
+        let item = msgraph::get_shared_drive_item(client_ctx.clone(), token.clone(), new_url.clone(), None).await?;
         println!("item:\n{:?}", item);
 
         Ok(())
@@ -79,11 +104,911 @@ mod tests {
         let url = Url::parse(MOD_LINK)?;
         let client_ctx = ClientCtx::build()?;
         let token = msgraph::login(&client_ctx.client).await?;
-        let item = msgraph::get_shared_drive_item(client_ctx.client.clone(), token.clone(), url).await?;
+        let item = msgraph::get_shared_drive_item(client_ctx.clone(), token.clone(), url, None).await?;
         println!("item:\n{:?}", item);
         if let FsEntryType::Folder { child_count: _ } = item.item {
             panic!("shared drive item is folder not file");
         }
         Ok(())
     }
+
+    #[tokio::test]
+    async fn progress_pool_bounds_slots() -> Result<(), Error> {
+        let pool = download::ProgressPool::new(vec![ProgressBar::hidden(), ProgressBar::hidden()]);
+        let cancel = CancellationToken::new();
+        let a = pool.acquire(&cancel).await?.unwrap();
+        let _b = pool.acquire(&cancel).await?.unwrap();
+
+        //pool is exhausted, so a cancelled wait should give up rather than block
+        let waiting = CancellationToken::new();
+        waiting.cancel();
+        assert!(pool.acquire(&waiting).await?.is_none());
+
+        drop(a);
+        assert!(pool.acquire(&cancel).await?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        use std::time::Duration;
+        use download::{DownloadError, RetryPolicy, is_transient};
+        use reqwest::StatusCode;
+
+        let retry = RetryPolicy { initial_backoff_ms: 100, max_backoff_ms: 1000, ..Default::default() };
+        for attempt in 0..64 {
+            let cap = Duration::from_millis((100u64 << attempt.min(10)).min(1000));
+            let delay = retry.backoff(attempt);
+            assert!(delay <= cap && delay >= cap / 2, "attempt {}: {:?} not within {:?}", attempt, delay, cap);
+        }
+
+        assert!(is_transient(&DownloadError::Timeout.into()));
+        assert!(is_transient(&DownloadError::Http(StatusCode::SERVICE_UNAVAILABLE).into()));
+        assert!(!is_transient(&DownloadError::Http(StatusCode::NOT_FOUND).into()));
+        assert!(!is_transient(&std::io::Error::other("disk full").into()));
+    }
+
+    #[test]
+    fn throttle_pauses_host() {
+        use std::time::Duration;
+        use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        let throttle = Throttle::default();
+        throttle.pause("graph.microsoft.com", Duration::from_secs(30));
+        //a shorter pause doesnt cut an existing one short
+        throttle.pause("graph.microsoft.com", Duration::from_secs(1));
+        assert!(throttle.remaining("graph.microsoft.com").unwrap() > Duration::from_secs(20));
+        assert!(throttle.remaining("tinyurl.com").is_none());
+    }
+
+    #[tokio::test]
+    async fn throttling_is_bounded_by_retry_budget() -> Result<(), Error> {
+        use std::{io::{Read, Write}, net::TcpListener, time::{Duration, Instant}};
+        use download::{download_file, DownloadError, RetryPolicy};
+
+        //asks every request to come back in an hour
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/file.bin", listener.local_addr()?))?;
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0u8; 4096]);
+                let _ = stream.write_all(b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            }
+        });
+
+        let dir = test_dir("throttle_budget")?;
+        let retry = RetryPolicy { initial_backoff_ms: 100, max_backoff_ms: 100, max_total_wait_secs: 2, ..Default::default() };
+        let start = Instant::now();
+        let err = download_file(ClientCtx::build()?, "file.bin".into(), url, None, &dir, None, &mut ProgressBar::hidden(), "file.bin", None, None, retry, CancellationToken::new()).await.unwrap_err();
+        assert!(start.elapsed() < retry.max_total_wait(), "waited {:?}", start.elapsed());
+        assert!(matches!(err.downcast_ref(), Some(DownloadError::Throttled { .. })), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn quick_xor_hash() {
+        use quickxor::QuickXorHash;
+
+        assert_eq!(QuickXorHash::new().finalize_base64(), "AAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+
+        //single byte lands in the first cell, length is xored in at byte 12
+        let mut h = QuickXorHash::new();
+        h.update(b"J");
+        let mut expected = [0u8; 20];
+        expected[0] = b'J';
+        expected[12] = 1;
+        assert_eq!(h.finalize(), expected);
+
+        //known answers from a bit by bit implementation of the spec: byte i xored in at bit (i * 11) mod 160 of a 160 bit
+        //little endian register, then the length at byte 12. longer than 160 bytes wraps the shift around the register
+        let hash = |data: &[u8]| {
+            let mut h = QuickXorHash::new();
+            h.update(data);
+            h.finalize_base64()
+        };
+        assert_eq!(hash(b"The quick brown fox jumps over the lazy dog"), "bMSlbysmxJL6S75XwfMcQZOpcr4=");
+        assert_eq!(hash(&(0..1000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>()), "KbphcpColXb1/3Wm950vUzeX1es=");
+
+        //same result however the stream is split up
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let mut whole = QuickXorHash::new();
+        whole.update(&data);
+        assert_eq!(whole.finalize_base64(), "4ipJBsO0INhWC22YKKB+RYVcmgI=");
+        for chunk in [1, 7, 159, 160, 161, 16 * 1024] {
+            let mut h = QuickXorHash::new();
+            data.chunks(chunk).for_each(|c| h.update(c));
+            assert_eq!(h.finalize_base64(), whole.finalize_base64(), "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn tmp_download_manifest_clean() -> Result<(), Error> {
+        use configs::{CACDownloadManifest, TmpDownloadID};
+
+        let dir = test_dir("manifest_clean")?;
+        std::fs::write(dir.join("@ace.7z.001"), b"partial")?;
+        std::fs::write(dir.join("orphan.7z"), b"partial")?;
+
+        let id = TmpDownloadID { id: "item".into(), etag: "v1".into() };
+        let mut manifest = CACDownloadManifest::default();
+        manifest.0.insert("@ace.7z.001".into(), id.clone());
+        manifest.0.insert("gone.7z".into(), id.clone());
+
+        assert!(manifest.can_resume("@ace.7z.001", &id));
+        assert!(!manifest.can_resume("@ace.7z.001", &TmpDownloadID { id: "item".into(), etag: "v2".into() }));
+
+        manifest.clean(&dir)?;
+        assert!(dir.join("@ace.7z.001").exists());
+        assert!(!dir.join("orphan.7z").exists());
+        assert_eq!(manifest.0.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_disposition_filenames() -> Result<(), Error> {
+        use download::{content_disposition_filename, sanitise_filename, response_filename};
+        use reqwest::header::{HeaderMap, CONTENT_DISPOSITION};
+
+        assert_eq!(content_disposition_filename(r#"attachment; filename="@ace.7z.001""#).as_deref(), Some("@ace.7z.001"));
+        assert_eq!(content_disposition_filename(r#"attachment; filename="a;b.7z"; size=10"#).as_deref(), Some("a;b.7z"));
+        assert_eq!(content_disposition_filename(r#"attachment; filename=plain.zip; size=10"#).as_deref(), Some("plain.zip"));
+        assert_eq!(content_disposition_filename(r#"attachment; filename="fallback.7z"; filename*=UTF-8''na%C3%AFve%20mod.7z"#).as_deref(), Some("naïve mod.7z"));
+        assert_eq!(content_disposition_filename(r#"attachment; filename="say \"hi\".7z""#).as_deref(), Some(r#"say "hi".7z"#));
+        assert_eq!(content_disposition_filename("inline"), None);
+
+        assert_eq!(sanitise_filename("../../evil.7z")?, "evil.7z");
+        assert_eq!(sanitise_filename(r"C:\Windows\evil.7z")?, "evil.7z");
+        assert_eq!(sanitise_filename("/etc/passwd")?, "passwd");
+        assert_eq!(sanitise_filename("con.7z")?, "_con.7z");
+        assert_eq!(sanitise_filename("what?.7z. ")?, "what_.7z");
+        assert!(sanitise_filename("..").is_err());
+        assert!(sanitise_filename("dir/").is_err());
+
+        let url = Url::parse("https://example.com/files/%40cba_a3.7z")?;
+        assert_eq!(response_filename(&HeaderMap::new(), &url)?, "@cba_a3.7z");
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_DISPOSITION, r#"attachment; filename="../x.7z""#.parse()?);
+        assert_eq!(response_filename(&headers, &url)?, "x.7z");
+        Ok(())
+    }
+
+    #[test]
+    fn extract_native_archives() -> Result<(), Error> {
+        use extract::{extract, ExtractError};
+        use std::io::Write;
+
+        let dir = test_dir("extract_native")?;
+        let src = dir.join("src").join("@mod");
+        std::fs::create_dir_all(src.join("addons"))?;
+        let pbo: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        std::fs::write(src.join("addons").join("mod.pbo"), &pbo)?;
+        std::fs::write(src.join("mod.cpp"), b"name = \"mod\";")?;
+
+        //7z split into byte volumes like the ones on sharepoint
+        let archive = dir.join("@mod.7z");
+        sevenz_rust2::compress_to_path(dir.join("src"), &archive)?;
+        let data = std::fs::read(&archive)?;
+        for (i, part) in data.chunks(data.len() / 3 + 1).enumerate() {
+            std::fs::write(dir.join(format!("@mod.7z.{:03}", i + 1)), part)?;
+        }
+        assert_eq!(extract::volumes(&dir.join("@mod.7z.001")).len(), 3);
+
+        let bar = ProgressBar::hidden();
+        extract(&dir.join("@mod.7z.001"), &dir.join("out7z"), None, Some(&bar), &CancellationToken::new())?;
+        assert_eq!(std::fs::read(dir.join("out7z/@mod/addons/mod.pbo"))?, pbo);
+        assert_eq!(bar.position(), bar.length().unwrap());
+
+        let zip_path = dir.join("@mod.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+            zip.start_file("@mod/addons/mod.pbo", zip::write::SimpleFileOptions::default())?;
+            zip.write_all(&pbo)?;
+            zip.finish()?;
+        }
+        extract(&zip_path, &dir.join("outzip"), None, None, &CancellationToken::new())?;
+        assert_eq!(std::fs::read(dir.join("outzip/@mod/addons/mod.pbo"))?, pbo);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(extract(&zip_path, &dir.join("cancelled"), None, None, &cancel), Err(ExtractError::Cancelled)));
+
+        let evil = dir.join("evil.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&evil)?);
+            zip.start_file("../evil.txt", zip::write::SimpleFileOptions::default())?;
+            zip.write_all(b"evil")?;
+            zip.finish()?;
+        }
+        assert!(matches!(extract(&evil, &dir.join("outevil"), None, None, &CancellationToken::new()), Err(ExtractError::UnsafePath(_))));
+        assert!(!dir.join("evil.txt").exists());
+
+        std::fs::write(dir.join("not_an_archive.rar"), b"Rar!....")?;
+        assert!(matches!(extract(&dir.join("not_an_archive.rar"), &dir.join("outrar"), None, None, &CancellationToken::new()), Err(ExtractError::UnsupportedFormat(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn extract_encrypted_archives() -> Result<(), Error> {
+        use extract::{extract, ExtractError};
+        use std::io::Write;
+
+        let dir = test_dir("extract_encrypted")?;
+        std::fs::create_dir_all(dir.join("src").join("dlc"))?;
+        std::fs::write(dir.join("src").join("dlc").join("dlc.pbo"), b"dlc content")?;
+
+        let sevenz = dir.join("dlc.7z");
+        sevenz_rust2::compress_to_path_encrypted(dir.join("src"), &sevenz, sevenz_rust2::Password::new("secret"))?;
+        let zip_path = dir.join("dlc.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+            zip.start_file("dlc/dlc.pbo", zip::write::SimpleFileOptions::default().with_aes_encryption(zip::AesMode::Aes256, "secret"))?;
+            zip.write_all(b"dlc content")?;
+            zip.finish()?;
+        }
+
+        for archive in [&sevenz, &zip_path] {
+            let out = dir.join("out");
+            let cancel = CancellationToken::new();
+            assert!(matches!(extract(archive, &out, Some("wrong"), None, &cancel), Err(ExtractError::WrongPassword)), "{}", archive.display());
+            assert!(matches!(extract(archive, &out, None, None, &cancel), Err(ExtractError::PasswordRequired)), "{}", archive.display());
+            extract(archive, &out, Some("secret"), None, &cancel)?;
+            assert_eq!(std::fs::read(out.join("dlc").join("dlc.pbo"))?, b"dlc content");
+            std::fs::remove_dir_all(&out)?;
+
+            //a damaged download isn't a wrong password
+            let data = std::fs::read(archive)?;
+            let truncated = dir.join("truncated").with_extension(archive.extension().unwrap());
+            std::fs::write(&truncated, &data[..data.len() / 2])?;
+            let err = extract(&truncated, &out, Some("secret"), None, &cancel);
+            assert!(matches!(err, Err(ExtractError::Corrupt(_) | ExtractError::Io(_))), "{}: {:?}", archive.display(), err);
+            let _ = std::fs::remove_dir_all(&out);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn install_dlc_is_staged() -> Result<(), Error> {
+        use extract::install_dlc;
+
+        let dir = test_dir("install_dlc")?;
+        std::fs::create_dir_all(dir.join("src").join("gm"))?;
+        std::fs::write(dir.join("src").join("gm").join("gm.pbo"), b"new")?;
+        let archive = dir.join("gm.7z");
+        sevenz_rust2::compress_to_path_encrypted(dir.join("src"), &archive, sevenz_rust2::Password::new("secret"))?;
+
+        let arma = dir.join("arma");
+        std::fs::create_dir_all(arma.join("gm"))?;
+        std::fs::write(arma.join("gm").join("old.pbo"), b"old")?;
+        let cancel = CancellationToken::new();
+        assert!(install_dlc(&archive, &arma, "gm", "wrong", None, &cancel).is_err());
+        assert_eq!(std::fs::read_dir(&arma)?.count(), 1, "staging folder left behind");
+        assert!(arma.join("gm").join("old.pbo").is_file());
+
+        install_dlc(&archive, &arma, "gm", "secret", None, &cancel)?;
+        assert_eq!(std::fs::read(arma.join("gm").join("gm.pbo"))?, b"new");
+        assert!(!arma.join("gm").join("old.pbo").exists());
+        assert_eq!(std::fs::read_dir(&arma)?.count(), 1);
+
+        //nothing is replaced if any entry can't be
+        std::fs::create_dir_all(dir.join("src").join("vn"))?;
+        std::fs::write(dir.join("src").join("vn").join("vn.pbo"), b"new")?;
+        let archive = dir.join("gm_vn.7z");
+        sevenz_rust2::compress_to_path_encrypted(dir.join("src"), &archive, sevenz_rust2::Password::new("secret"))?;
+        std::fs::remove_dir_all(arma.join("gm"))?;
+        std::fs::create_dir_all(arma.join("gm"))?;
+        std::fs::write(arma.join("gm").join("old.pbo"), b"old")?;
+        std::fs::write(arma.join("vn"), b"not a folder")?;
+        assert!(install_dlc(&archive, &arma, "gm_vn", "secret", None, &cancel).is_err());
+        assert!(arma.join("gm").join("old.pbo").is_file());
+        assert!(arma.join("vn").is_file());
+        assert_eq!(std::fs::read_dir(&arma)?.count(), 2);
+
+        //the backup of an interrupted install is put back before swapping
+        std::fs::remove_file(arma.join("vn"))?;
+        std::fs::create_dir_all(arma.join(".vn.backup"))?;
+        std::fs::write(arma.join(".vn.backup").join("old.pbo"), b"old")?;
+        install_dlc(&archive, &arma, "gm_vn", "secret", None, &cancel)?;
+        assert_eq!(std::fs::read(arma.join("vn").join("vn.pbo"))?, b"new");
+        assert!(!arma.join("vn").join("old.pbo").exists());
+        assert_eq!(std::fs::read_dir(&arma)?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn install_mod_finds_mod_root() -> Result<(), Error> {
+        use extract::{install_mod, ExtractError};
+        use std::io::Write;
+
+        let dir = test_dir("install_mod")?;
+        let mod_dir = dir.join("mods");
+        std::fs::create_dir_all(mod_dir.join("@ace").join("addons"))?;
+        std::fs::write(mod_dir.join("@ace").join("addons").join("old.pbo"), b"old")?;
+
+        let make_zip = |path: &Path, files: &[&str]| -> Result<(), Error> {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+            for f in files {
+                zip.start_file(*f, zip::write::SimpleFileOptions::default())?;
+                zip.write_all(f.as_bytes())?;
+            }
+            zip.finish()?;
+            Ok(())
+        };
+
+        //nested and misnamed
+        let nested = dir.join("nested.zip");
+        make_zip(&nested, &["wrapper/@ACE3/addons/ace_main.pbo", "wrapper/@ACE3/mod.cpp", "wrapper/readme.txt"])?;
+        install_mod(&nested, &mod_dir, "@ace", None, &CancellationToken::new())?;
+        assert!(mod_dir.join("@ace").join("addons").join("ace_main.pbo").is_file());
+        assert!(!mod_dir.join("@ace").join("addons").join("old.pbo").exists());
+        assert_eq!(std::fs::read_dir(&mod_dir)?.count(), 1, "staging folder left behind");
+
+        //addons at the top level
+        let flat = dir.join("flat.zip");
+        make_zip(&flat, &["addons/cba_main.pbo", "mod.cpp"])?;
+        install_mod(&flat, &mod_dir, "@cba", None, &CancellationToken::new())?;
+        assert!(mod_dir.join("@cba").join("addons").join("cba_main.pbo").is_file());
+
+        let several = dir.join("several.zip");
+        make_zip(&several, &["@a/addons/a.pbo", "@a/mod.cpp", "@b/addons/b.pbo", "@b/mod.cpp"])?;
+        let err = install_mod(&several, &mod_dir, "@c", None, &CancellationToken::new()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExtractError>(), Some(ExtractError::ModLayout { .. })), "{}", err);
+        assert!(install_mod(&several, &mod_dir, "@b", None, &CancellationToken::new()).is_ok());
+
+        let empty = dir.join("empty.zip");
+        make_zip(&empty, &["@x/readme.txt"])?;
+        let err = install_mod(&empty, &mod_dir, "@x", None, &CancellationToken::new()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExtractError>(), Some(ExtractError::ModLayout { .. })), "{}", err);
+        assert!(!mod_dir.join("@x").exists());
+        assert_eq!(std::fs::read_dir(&mod_dir)?.count(), 3, "staging folder left behind");
+
+        Ok(())
+    }
+
+    #[test]
+    fn install_mod_keeps_old_version_on_failure() -> Result<(), Error> {
+        use extract::{install_mod, ExtractError};
+        use std::io::Write;
+
+        let dir = test_dir("install_mod_rollback")?;
+        let mod_dir = dir.join("mods");
+        let old = mod_dir.join("@ace").join("addons").join("old.pbo");
+        std::fs::create_dir_all(old.parent().unwrap())?;
+        std::fs::write(&old, b"old")?;
+
+        let good = dir.join("good.zip");
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&good)?);
+            zip.start_file("@ace/addons/new.pbo", zip::write::SimpleFileOptions::default())?;
+            zip.write_all(b"new")?;
+            zip.finish()?;
+        }
+        std::fs::write(dir.join("corrupt.zip"), b"PK not really a zip")?;
+
+        assert!(install_mod(&dir.join("corrupt.zip"), &mod_dir, "@ace", None, &CancellationToken::new()).is_err());
+        assert!(old.is_file());
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = install_mod(&good, &mod_dir, "@ace", None, &cancel).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExtractError>(), Some(ExtractError::Cancelled)), "{}", err);
+        assert!(old.is_file());
+
+        //crashed after moving the old version aside
+        std::fs::rename(mod_dir.join("@ace"), mod_dir.join(".@ace.backup"))?;
+        assert!(install_mod(&dir.join("corrupt.zip"), &mod_dir, "@ace", None, &CancellationToken::new()).is_err());
+        assert!(old.is_file());
+
+        install_mod(&good, &mod_dir, "@ace", None, &CancellationToken::new())?;
+        assert!(!old.exists());
+        assert!(mod_dir.join("@ace").join("addons").join("new.pbo").is_file());
+        assert_eq!(std::fs::read_dir(&mod_dir)?.count(), 1, "backup or staging folder left behind");
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_volume_order() -> Result<(), Error> {
+        use extract::{parse_volume, sort_volumes, group_volumes, Volume, ExtractError};
+
+        assert_eq!(parse_volume("@ace.7z.002"), Volume { archive: "@ace.7z".into(), number: 2 });
+        assert_eq!(parse_volume("@ace.zip.010"), Volume { archive: "@ace.zip".into(), number: 10 });
+        assert_eq!(parse_volume("@rhs.part3.rar"), Volume { archive: "@rhs.rar".into(), number: 3 });
+        assert_eq!(parse_volume("@cba.7z"), Volume { archive: "@cba.7z".into(), number: 1 });
+
+        fn name<'a>(s: &'a &str) -> &'a str { s }
+        assert_eq!(sort_volumes(vec!["a.7z.003", "a.7z.001", "a.7z.002"], name)?, vec!["a.7z.001", "a.7z.002", "a.7z.003"]);
+        assert_eq!(sort_volumes(vec!["a.part10.rar", "a.part2.rar", "a.part1.rar", "a.part3.rar", "a.part4.rar", "a.part5.rar", "a.part6.rar", "a.part7.rar", "a.part8.rar", "a.part9.rar"], name)?[..3], ["a.part1.rar", "a.part2.rar", "a.part3.rar"]);
+        assert_eq!(sort_volumes(vec!["a.7z.001", "a.7z.000"], name)?, vec!["a.7z.000", "a.7z.001"]);
+        assert!(matches!(sort_volumes(vec!["a.7z.001", "a.7z.003"], name), Err(ExtractError::BadVolumes { .. })));
+        assert!(matches!(sort_volumes(vec!["a.7z.002", "a.7z.003"], name), Err(ExtractError::BadVolumes { .. })));
+        assert!(matches!(sort_volumes(vec!["a.7z.001", "a.7z.001"], name), Err(ExtractError::BadVolumes { .. })));
+        assert!(matches!(sort_volumes(vec!["a.7z.001", "b.7z.002"], name), Err(ExtractError::BadVolumes { .. })));
+
+        let groups = group_volumes(vec!["b.7z", "a.zip.002", "a.zip.001"], name)?;
+        assert_eq!(groups, vec![("b.7z".to_string(), vec!["b.7z"]), ("a.zip".to_string(), vec!["a.zip.001", "a.zip.002"])]);
+        Ok(())
+    }
+
+    #[test]
+    fn dirhash_cache_reuses_unchanged_files() -> Result<(), Error> {
+        use dirhash::{hash_directory, hash_directory_cached, FolderCache, HashProgress, LinkPolicy};
+
+        let dir = test_dir("dirhash_cache")?;
+        std::fs::create_dir_all(dir.join("addons"))?;
+        std::fs::write(dir.join("addons").join("a.pbo"), b"aaaa")?;
+        std::fs::write(dir.join("mod.cpp"), b"")?;
+
+        let mut cache = FolderCache::new();
+        let full = hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, false, &HashProgress::default(), &CancellationToken::new())?;
+        assert_eq!(full, hash_directory(&dir)?);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains_key("addons/a.pbo"));
+
+        //a stale digest is trusted while size and mtime match, and ignored when forced
+        cache.get_mut("mod.cpp").unwrap().hash = "stale".into();
+        assert_ne!(hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, false, &HashProgress::default(), &CancellationToken::new())?, full);
+        assert_eq!(hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, true, &HashProgress::default(), &CancellationToken::new())?, full);
+
+        std::fs::remove_file(dir.join("mod.cpp"))?;
+        hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, false, &HashProgress::default(), &CancellationToken::new())?;
+        assert_eq!(cache.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mod_manifest_diff_and_repair() -> Result<(), Error> {
+        use dirhash::{FolderCache, HashProgress, LinkPolicy, ModManifest};
+
+        let dir = test_dir("mod_manifest")?;
+        let mod_dir = dir.join("mods");
+        let ace = mod_dir.join("@ace");
+        std::fs::create_dir_all(ace.join("addons"))?;
+        std::fs::write(ace.join("addons").join("main.pbo"), b"main")?;
+        std::fs::write(ace.join("addons").join("medical.pbo"), b"medical")?;
+        std::fs::write(ace.join("mod.cpp"), b"name = \"ace\";")?;
+
+        let expected = ModManifest::build(&ace, LinkPolicy::HashAsLink, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new())?;
+        assert_eq!(expected.files.len(), 3);
+        assert!(expected.diff(&expected).is_empty());
+
+        std::fs::write(ace.join("addons").join("main.pbo"), b"corrupt")?;
+        std::fs::remove_file(ace.join("addons").join("medical.pbo"))?;
+        std::fs::write(ace.join("addons").join("extra.pbo"), b"extra")?;
+        let diff = expected.diff(&ModManifest::build(&ace, LinkPolicy::HashAsLink, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new())?);
+        assert_eq!(diff.added, vec!["addons/extra.pbo"]);
+        assert_eq!(diff.removed, vec!["addons/medical.pbo"]);
+        assert_eq!(diff.modified, vec!["addons/main.pbo"]);
+
+        //only the needed files, laid out like the mod as a delta update stages them
+        let src = dir.join("staged");
+        std::fs::create_dir_all(src.join("addons"))?;
+        std::fs::write(src.join("addons").join("main.pbo"), b"main")?;
+        std::fs::write(src.join("addons").join("medical.pbo"), b"medical")?;
+        std::fs::write(ace.join("userconfig.hpp"), b"untouched")?;
+        let mut diff = diff;
+        diff.added.clear(); //leave the extra file alone, checks only listed files are touched
+        expected.repair(&ace, &src, &diff)?;
+        assert_eq!(std::fs::read(ace.join("addons").join("main.pbo"))?, b"main");
+        assert_eq!(std::fs::read(ace.join("addons").join("medical.pbo"))?, b"medical");
+        assert!(ace.join("userconfig.hpp").exists());
+
+        let after = expected.diff(&ModManifest::build(&ace, LinkPolicy::HashAsLink, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new())?);
+        assert!(after.removed.is_empty() && after.modified.is_empty());
+        expected.repair(&ace, &ace, &after)?;
+        assert!(expected.diff(&ModManifest::build(&ace, LinkPolicy::HashAsLink, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new())?).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_mods_against_published_manifest() -> Result<(), Error> {
+        use dirhash::{verify_mods, FolderCache, HashProgress, LinkPolicy, ModManifest, ModsManifest};
+        use configs::HashCache;
+
+        let dir = test_dir("verify_mods")?;
+        for m in ["@ace", "@cba"] {
+            std::fs::create_dir_all(dir.join(m).join("addons"))?;
+            std::fs::write(dir.join(m).join("addons").join("main.pbo"), m.as_bytes())?;
+        }
+
+        let mut published = ModsManifest::default();
+        for m in ["@ace", "@cba", "@not_installed"] {
+            let path = match m { "@not_installed" => dir.join("@ace"), _ => dir.join(m) };
+            published.0.insert(m.to_string(), ModManifest::build(&path, LinkPolicy::HashAsLink, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new())?);
+        }
+        std::fs::write(dir.join("@cba").join("addons").join("main.pbo"), b"corrupted")?;
+
+        let names: Vec<String> = ["@ace", "@cba", "@not_installed", "@unpublished"].iter().map(|s| s.to_string()).collect();
+        let mut cache = HashCache::default();
+        let report = verify_mods(&dir, &published, &names, &mut cache, false, &HashProgress::default(), &CancellationToken::new())?;
+        assert_eq!(report.keys().collect::<Vec<_>>(), vec!["@ace", "@cba"]);
+        assert!(report["@ace"].is_empty());
+        assert_eq!(report["@cba"].modified, vec!["addons/main.pbo"]);
+        assert_eq!(report["@cba"].report(10), vec!["modified: addons/main.pbo"]);
+        assert_eq!(cache.0.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dirhash_progress_and_cancel() -> Result<(), Error> {
+        use dirhash::{hash_directory_cached, DirHashError, FolderCache, HashProgress, LinkPolicy};
+
+        let dir = test_dir("dirhash_progress")?;
+        std::fs::create_dir_all(dir.join("addons"))?;
+        std::fs::write(dir.join("addons").join("a.pbo"), vec![1u8; 1000])?;
+        std::fs::write(dir.join("addons").join("b.pbo"), vec![2u8; 24])?;
+
+        let progress = HashProgress::new(Some(ProgressBar::hidden()));
+        let mut cache = FolderCache::new();
+        hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, false, &progress, &CancellationToken::new())?;
+        assert_eq!(progress.files(), (2, 2));
+        assert_eq!(progress.bytes(), (1024, 1024));
+
+        //cached files still count towards the progress
+        let progress = HashProgress::default();
+        hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut cache, false, &progress, &CancellationToken::new())?;
+        assert_eq!(progress.bytes(), (1024, 1024));
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = hash_directory_cached(&dir, LinkPolicy::HashAsLink, &mut FolderCache::new(), true, &HashProgress::default(), &cancel).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DirHashError::Cancelled)));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn dirhash_link_policy() -> Result<(), Error> {
+        use dirhash::{DirHashError, FolderCache, HashProgress, LinkPolicy, ModManifest};
+        use std::os::unix::fs::symlink;
+
+        let dir = test_dir("dirhash_links")?;
+        let ace = dir.join("@ace");
+        let elsewhere = dir.join("elsewhere");
+        std::fs::create_dir_all(ace.join("addons"))?;
+        std::fs::create_dir_all(&elsewhere)?;
+        std::fs::write(elsewhere.join("big.pbo"), b"big")?;
+        symlink(&elsewhere, ace.join("addons").join("junction"))?;
+
+        let build = |policy| ModManifest::build(&ace, policy, &mut FolderCache::new(), false, &HashProgress::default(), &CancellationToken::new());
+        let followed = build(LinkPolicy::Follow)?;
+        assert_eq!(followed.files.keys().collect::<Vec<_>>(), vec!["addons/junction/big.pbo"]);
+        let linked = build(LinkPolicy::HashAsLink)?;
+        assert_eq!(linked.files["addons/junction"].link.as_deref(), elsewhere.to_str());
+        let err = build(LinkPolicy::Reject).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DirHashError::LinkRejected(_))));
+
+        //the policy travels with the manifest
+        let json = serde_json::to_string(&linked)?;
+        assert!(json.contains("\"linkPolicy\":\"hashAsLink\""));
+        assert_eq!(serde_json::from_str::<ModManifest>(&json)?, linked);
+
+        //relative link back up to the mod folder
+        symlink("../..", ace.join("addons").join("loop"))?;
+        let err = build(LinkPolicy::Follow).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DirHashError::LinkCycle { .. })), "{}", err);
+        assert!(build(LinkPolicy::HashAsLink).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mod_manifest_blob_urls() -> Result<(), Error> {
+        use dirhash::{LinkPolicy, ModManifest};
+
+        //manifests published before blob urls and link policies still parse
+        let old: ModManifest = serde_json::from_str(r#"{"files":{"addons/main.pbo":{"size":4,"hash":"ab"}}}"#)?;
+        assert_eq!(old.link_policy, LinkPolicy::HashAsLink);
+        assert_eq!(old.blob("addons/main.pbo"), None);
+
+        let mut m = old.clone();
+        m.blob_url = Some("https://example.com/blobs/".into());
+        assert_eq!(m.blob("addons/main.pbo").as_deref(), Some("https://example.com/blobs/ab"));
+        assert_eq!(m.blob("addons/missing.pbo"), None);
+        assert!(serde_json::to_string(&m)?.contains("\"blobUrl\""));
+        Ok(())
+    }
+
+    #[test]
+    fn config_lock_is_exclusive() -> Result<(), Error> {
+        use configs::ConfigLock;
+        use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+        let path = PathBuf::from(tmp_dir()?).join("locked.json");
+        let lock = ConfigLock::acquire(&path)?;
+        let taken = Arc::new(AtomicBool::new(false));
+        let t = {
+            let (path, taken) = (path.clone(), taken.clone());
+            std::thread::spawn(move || {
+                let _lock = ConfigLock::acquire(&path).unwrap();
+                taken.store(true, Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!taken.load(Ordering::SeqCst));
+        drop(lock);
+        t.join().unwrap();
+        assert!(taken.load(Ordering::SeqCst));
+        std::fs::remove_file(path.with_file_name("locked.json.lock"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn config_save_and_read_any_path() -> Result<(), Error> {
+        use configs::{CACContent, Config, Links};
+
+        let dir = test_dir("config_paths")?;
+        let path = dir.join("content.json");
+
+        let mut content = CACContent::default();
+        content.mods.insert("@ace".into(), Links::Single("https://example.com/ace.7z".into()));
+        content.save_to(&path)?;
+        assert!(path.is_file());
+        assert_eq!(CACContent::read_from(&path)?.mods, content.mods);
+
+        CACContent::update_at(&path, |c| {
+            c.mods.clear();
+            Ok(())
+        })?;
+        assert!(CACContent::read_from(&path)?.mods.is_empty());
+
+        //a failed update leaves the file alone
+        content.save_to(&path)?;
+        assert!(CACContent::update_at(&path, |c| -> Result<(), Error> {
+            c.mods.clear();
+            Err(anyhow!("nope"))
+        }).is_err());
+        assert_eq!(CACContent::read_from(&path)?.mods, content.mods);
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_migrates_and_keeps_unknown_fields() -> Result<(), Error> {
+        use configs::{CACConfig, Config};
+
+        let dir = test_dir("config_migrate")?;
+        let path = dir.join("config.json");
+        let old = r#"{"username":"a","armaPath":"arma3_x64.exe","serverPassword":"","optionalsOn":false,
+            "enabledOptionals":[],"pendingUpdates":[],"modDir":"Mods","futureThing":[1,2]}"#;
+        std::fs::write(&path, old)?;
+
+        let config = CACConfig::read_from(&path)?;
+        assert_eq!(config.version as usize, CACConfig::MIGRATIONS.len());
+        assert_eq!(config.extra["futureThing"], serde_json::json!([1, 2]));
+        assert_eq!(config.profile()?.username, "a");
+        assert_eq!(config.active_profile, configs::DEFAULT_PROFILE);
+        assert_eq!(std::fs::read_to_string(dir.join("config.json.v0.bak"))?, old);
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(saved["version"], serde_json::json!(CACConfig::MIGRATIONS.len()));
+
+        config.save_to(&path)?;
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(saved["futureThing"], serde_json::json!([1, 2]));
+
+        //a config from a newer launcher is read as is
+        let mut newer = saved.clone();
+        newer["version"] = serde_json::json!(99);
+        std::fs::write(&path, newer.to_string())?;
+        assert_eq!(CACConfig::read_from(&path)?.version, 99);
+        assert!(!dir.join("config.json.v99.bak").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_salvage_truncated_file() -> Result<(), Error> {
+        use configs::salvage_object;
+
+        let text = r#"{"mods": {"@ace": "https://a", "@cba": ["https://b.001", "https://b.0"#;
+        let salvaged = salvage_object(text);
+        assert_eq!(salvaged["mods"], serde_json::json!({"@ace": "https://a"}));
+
+        let text = r#"{"optionals": {}, "mods": 5, "dlc": {"#;
+        let salvaged = salvage_object(text);
+        assert_eq!(salvaged.keys().collect::<Vec<_>>(), vec!["dlc", "mods", "optionals"]);
+        assert!(salvage_object("not json").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn config_backups_and_rebuild() -> Result<(), Error> {
+        use configs::{rebuild, restore_backup, CACConfig, CACContent, Config, ConfigError, Links};
+
+        let dir = test_dir("config_recovery")?;
+        let path = dir.join("content.json");
+
+        let mut content = CACContent::default();
+        for i in 0..5 {
+            content.mods.insert(format!("@mod{}", i), Links::Single("https://example.com".into()));
+            content.save_to(&path)?;
+        }
+        assert!(dir.join("content.json.3.bak").is_file());
+        assert!(!dir.join("content.json.4.bak").exists());
+        //backups are the saves before the current one
+        assert_eq!(CACContent::read_from(dir.join("content.json.1.bak"))?.mods.len(), 4);
+
+        //cut off part way through a save
+        let text = std::fs::read_to_string(&path)?;
+        std::fs::write(&path, &text[..text.find("@mod3").unwrap()])?;
+        let err = CACContent::read_from(&path).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ConfigError::Corrupt { .. })));
+
+        //unusable backups are skipped for the next newest
+        std::fs::write(dir.join("content.json.1.bak"), "garbage")?;
+        assert!(restore_backup::<CACContent>(&path)?);
+        assert_eq!(CACContent::read_from(&path)?.mods.len(), 3);
+
+        std::fs::write(&path, &text[..text.find("\"optionals\"").unwrap()])?;
+        let kept = rebuild::<CACContent>(&path, |_| Ok(CACContent::default()))?;
+        assert!(kept.contains(&"mods".to_string()));
+        assert_eq!(CACContent::read_from(&path)?.mods, content.mods);
+        assert!(dir.join("content.json.corrupt").is_file());
+
+        //what's salvaged from an older config is migrated before it's kept
+        let path = dir.join("config.json");
+        let old = r#"{"version":1,"username":"a","armaPath":"arma3_x64.exe","serverPassword":"pw","optionalsOn":false,
+            "enabledOptionals":[],"pendingUpdates":["@ace"],"modDir":"Mods"}"#;
+        std::fs::write(&path, &old[..old.find("\"modDir\"").unwrap()])?;
+        let kept = rebuild::<CACConfig>(&path, |_| Ok(CACConfig::with_arma_path("arma3_x64.exe".into())))?;
+        assert!(kept.contains(&"profiles".to_string()));
+        let config = CACConfig::read_from(&path)?;
+        assert_eq!(config.profile()?.username, "a");
+        assert_eq!(config.profile()?.server_password, "pw");
+        assert!(config.pending_updates.contains("@ace"));
+        assert!(!dir.join("config.json.v1.bak").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_folder_resolution() -> Result<(), Error> {
+        use configs::{resolve_config_folder, PORTABLE_MARKER};
+        use std::ffi::OsStr;
+
+        let exe_dir = test_dir("config_root")?;
+        let cwd = test_dir("config_cwd")?;
+        let platform = test_dir("config_platform")?;
+        let resolve = |flag: Option<&Path>, env: Option<&OsStr>| resolve_config_folder(flag, env, Some(&exe_dir), Some(&cwd), Some(platform.to_path_buf()));
+        let flag = Path::new("flag");
+        let env = OsStr::new("env");
+
+        assert_eq!(resolve(Some(flag), Some(env)), flag);
+        assert_eq!(resolve(None, Some(env)), Path::new("env"));
+        assert_eq!(resolve(None, Some(OsStr::new(""))), *platform);
+
+        //a config left in the working directory is used until the platform folder has one
+        std::fs::create_dir_all(cwd.join("CAC-Config"))?;
+        std::fs::write(cwd.join("CAC-Config").join("config.json"), "{}")?;
+        assert_eq!(resolve(None, None), cwd.join("CAC-Config"));
+        std::fs::write(platform.join("config.json"), "{}")?;
+        assert_eq!(resolve(None, None), *platform);
+
+        std::fs::write(exe_dir.join(PORTABLE_MARKER), "")?;
+        assert_eq!(resolve(None, None), exe_dir.join("CAC-Config"));
+
+        //existing installs keep their config next to the exe
+        std::fs::remove_file(exe_dir.join(PORTABLE_MARKER))?;
+        std::fs::create_dir_all(exe_dir.join("CAC-Config"))?;
+        std::fs::write(exe_dir.join("CAC-Config").join("config.json"), "{}")?;
+        assert_eq!(resolve(None, None), exe_dir.join("CAC-Config"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_profiles() -> Result<(), Error> {
+        use configs::CACConfig;
+
+        let mut config = CACConfig::with_arma_path("arma/arma3_x64.exe".into());
+        config.profile_mut()?.enabled_optionals.insert("@blastcore".into());
+        config.add_profile("alt")?;
+        assert!(config.add_profile("alt").is_err());
+        assert!(config.add_profile(" ").is_err());
+
+        config.switch_profile("alt")?;
+        assert_eq!(config.profile()?.username, "alt");
+        assert!(config.profile()?.enabled_optionals.is_empty());
+
+        config.rename_profile("alt", "main")?;
+        assert_eq!(config.active_profile, "main");
+        assert!(config.rename_profile("main", "default").is_err());
+
+        config.remove_profile("main")?;
+        assert_eq!(config.active_profile, "default");
+        assert!(config.profile()?.enabled_optionals.contains("@blastcore"));
+        assert!(config.remove_profile("default").is_err());
+        assert!(config.switch_profile("missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_settings() -> Result<(), Error> {
+        use configs::CACConfig;
+        use download::move_entries;
+
+        let dir = test_dir("settings")?;
+
+        let mut config = CACConfig::with_arma_path(dir.join("arma3_x64.exe").to_str().unwrap().into());
+        config.set_username("  player ")?;
+        assert_eq!(config.profile()?.username, "player");
+        assert!(config.set_username(" ").is_err());
+        assert!(config.set_username("a\"b").is_err());
+        config.set_server_password("secret")?;
+        assert!(config.set_server_password("bad\"").is_err());
+        assert_eq!(config.profile()?.server_password, "secret");
+
+        std::fs::write(dir.join("file"), b"")?;
+        assert!(config.set_mod_dir("file").is_err());
+        assert!(config.set_mod_dir("a;b").is_err());
+        config.set_mod_dir("NewMods")?;
+        assert_eq!(config.mod_dir(), "NewMods");
+        assert_eq!(config.absolute_mod_dir()?, dir.join("NewMods"));
+
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        let names = vec!["@ace".to_string(), "@cba".to_string(), "@tfar".to_string()];
+        std::fs::create_dir_all(old.join("@ace").join("addons"))?;
+        std::fs::write(old.join("@ace").join("addons").join("ace.pbo"), b"ace")?;
+        std::fs::create_dir_all(old.join("@other"))?;
+        std::fs::create_dir_all(new.join("@rhs"))?;
+        move_entries(&old, &new, &names)?;
+        assert_eq!(std::fs::read(new.join("@ace").join("addons").join("ace.pbo"))?, b"ace");
+        assert!(new.join("@rhs").is_dir());
+        assert!(old.join("@other").is_dir(), "only the named entries move");
+        assert!(!old.join("@ace").exists());
+
+        std::fs::create_dir_all(old.join("@ace"))?;
+        std::fs::create_dir_all(old.join("@cba"))?;
+        assert!(move_entries(&old, &new, &names).is_err());
+        assert!(old.join("@cba").is_dir() && !new.join("@cba").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_status() -> Result<(), Error> {
+        use configs::{CACConfig, CACContent, DLC, Links};
+        use servers::ModStatus;
+
+        let dir = test_dir("content_status")?;
+        std::fs::create_dir_all(dir.join("Mods").join("@ace"))?;
+        std::fs::create_dir_all(dir.join("Mods").join("@cba"))?;
+        std::fs::create_dir_all(dir.join("Mods").join("@rhs"))?;
+        std::fs::create_dir_all(dir.join("gm"))?;
+
+        let mut config = CACConfig::with_arma_path(dir.join("arma3_x64.exe").to_str().unwrap().into());
+        config.set_mod_dir("Mods")?;
+        config.pending_updates.extend(["@cba".to_string(), "@rhs".to_string()]);
+
+        let link = || Links::Single("https://example.com".into());
+        let mut content = CACContent::default();
+        for name in ["@ace", "@cba", "@rhs", "@tfar"] {
+            content.mods.insert(name.into(), link());
+        }
+        content.dlc.insert("gm".into(), DLC { link: link(), pwd: String::new(), description: String::new() });
+
+        config.failed_verification.extend(["@rhs".to_string(), "@ace".to_string()]);
+        assert_eq!(servers::content_status(&config, &content)?, vec![
+            ("@ace".to_string(), ModStatus::UpToDate),
+            ("@cba".to_string(), ModStatus::PendingUpdate),
+            ("@rhs".to_string(), ModStatus::Corrupt),
+            ("@tfar".to_string(), ModStatus::Missing),
+            ("gm".to_string(), ModStatus::UpToDate),
+        ]);
+
+        Ok(())
+    }
 }